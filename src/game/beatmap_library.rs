@@ -0,0 +1,152 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+
+use super::osu_reader;
+use super::ASSETS_PATH;
+
+//region Library Resources
+/// A single .osu difficulty found in the library, indexed by its [Metadata]
+#[derive(Debug, Clone)]
+pub struct LibraryBeatmap {
+    /// The folder of the beatmap set, e.g. assets/beatmaps/Some Set
+    pub directory: PathBuf,
+    pub file_name: String,
+    /// MD5 of the .osu file, which identifies the difficulty like in osu!
+    pub hash: String,
+    pub title: String,
+    pub artist: String,
+    pub creator: String,
+    /// The difficulty name
+    pub version: String,
+    pub overall_difficulty: f32,
+    pub audio_filename: String,
+    /// Where the song preview starts, in milliseconds (-1 if not set)
+    pub preview_time: i32,
+}
+
+impl LibraryBeatmap {
+    /// Path of the .osu file, to be read with the osu_reader
+    pub fn path(&self) -> PathBuf {
+        self.directory.join(&self.file_name)
+    }
+
+    /// Path of a file of this set for the AssetServer, which is relative to the assets folder
+    pub fn asset_path(&self, file: &str) -> String {
+        let directory = self
+            .directory
+            .strip_prefix(ASSETS_PATH)
+            .unwrap_or(&self.directory);
+
+        // Beatmaps made on Windows may use backslashes
+        directory
+            .join(file.replace('\\', "/"))
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+}
+
+/// Every difficulty inside the same folder
+#[derive(Debug, Clone)]
+pub struct BeatmapSet {
+    pub directory: PathBuf,
+    /// Sorted from easiest to hardest
+    pub difficulties: Vec<LibraryBeatmap>,
+}
+
+impl BeatmapSet {
+    /// The metadata of the set is the metadata of any of its difficulties
+    pub fn first(&self) -> &LibraryBeatmap {
+        &self.difficulties[0]
+    }
+}
+
+#[derive(Default)]
+pub struct BeatmapLibrary {
+    /// Sorted by artist and title
+    pub sets: Vec<BeatmapSet>,
+}
+
+/// The difficulty the player chose, to be loaded by beatmap_plugin::init_system
+#[derive(Clone)]
+pub struct SelectedBeatmap(pub LibraryBeatmap);
+//endregion
+
+impl BeatmapLibrary {
+    /// Index every .osu file in `path` and in its direct subfolders (one per beatmap set).
+    /// Files that can't be parsed are skipped
+    pub fn scan(path: &Path) -> Self {
+        let mut directories = vec![path.to_path_buf()];
+        if let Ok(entries) = fs::read_dir(path) {
+            directories.extend(
+                entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| path.is_dir()),
+            );
+        }
+
+        let mut sets: Vec<BeatmapSet> = directories
+            .into_iter()
+            .filter_map(|directory| {
+                let mut difficulties = scan_set(&directory);
+                if difficulties.is_empty() {
+                    return None;
+                }
+
+                difficulties.sort_by(|a, b| {
+                    a.overall_difficulty
+                        .total_cmp(&b.overall_difficulty)
+                        .then_with(|| a.version.cmp(&b.version))
+                });
+                Some(BeatmapSet {
+                    directory,
+                    difficulties,
+                })
+            })
+            .collect();
+
+        sets.sort_by_cached_key(|set| {
+            (
+                set.first().artist.to_lowercase(),
+                set.first().title.to_lowercase(),
+            )
+        });
+
+        BeatmapLibrary { sets }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+}
+
+fn scan_set(directory: &Path) -> Vec<LibraryBeatmap> {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "osu"))
+        .filter_map(|path| match osu_reader::open_osu(&path.to_string_lossy()) {
+            Ok(beatmap) => Some(LibraryBeatmap {
+                directory: directory.to_path_buf(),
+                file_name: path.file_name()?.to_string_lossy().to_string(),
+                hash: format!("{:x}", md5::compute(fs::read(&path).ok()?)),
+                title: beatmap.metadata.title,
+                artist: beatmap.metadata.artist,
+                creator: beatmap.metadata.creator,
+                version: beatmap.metadata.version,
+                overall_difficulty: beatmap.difficulty.overall_difficulty,
+                audio_filename: beatmap.general.audio_filename,
+                preview_time: beatmap.general.preview_time,
+            }),
+            Err(error) => {
+                warn!("Skipping beatmap: {error}");
+                None
+            }
+        })
+        .collect()
+}
@@ -1,38 +1,97 @@
 use crate::GameStates;
 use bevy::prelude::*;
-use bevy_kira_audio::{AudioApp, AudioChannel, AudioControl};
-use std::{collections::HashMap, time::Duration};
+use bevy_kira_audio::{AudioApp, AudioChannel, AudioControl, AudioInstance, PlaybackState};
+use std::{collections::HashSet, fmt, path::Path};
 
 use super::{
-    osu_reader::{self, OsuFileSection},
-    BEATMAP_FILE_NAME, BEATMAP_INITIAL_WAIT_TIME, BEATMAP_MUSIC_OFFSET_TIME, SectionsLoaded,
+    beatmap_library::SelectedBeatmap,
+    common_components::GameEntity,
+    mods::Mods,
+    AudioSettings,
+    osu_reader::{self, OsuBeatmap, OsuError},
+    despawn_with, is_game_state_criteria, loading_plugin::LoadingAssets, FontHandles,
+    BEATMAP_INITIAL_WAIT_TIME,
+    BEATMAP_MUSIC_OFFSET_TIME, SONG_CLOCK_CORRECTION, SONG_CLOCK_MAX_DRIFT,
 };
 
+/// The audio formats enabled in bevy_kira_audio's features
+const SUPPORTED_AUDIO_EXTENSIONS: [&str; 5] = ["mp3", "ogg", "oga", "wav", "flac"];
+
 pub struct BeatmapPlugin;
 
 impl Plugin for BeatmapPlugin {
     fn build(&self, app: &mut App) {
         app.add_system_set(SystemSet::on_enter(GameStates::Loading).with_system(init_system))
+            .add_system_set(
+                SystemSet::on_update(GameStates::Loading).with_system(load_error_screen_system),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameStates::Loading).with_system(despawn_with::<LoadErrorText>),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameStates::Loading).with_system(background_scaling_system),
+            )
             .add_system_set(
                 SystemSet::on_update(GameStates::Game)
-                    .with_system(beatmap_start_system)
                     .with_system(background_scaling_system),
             )
+            .add_system_set_to_stage(
+                CoreStage::PreUpdate,
+                SystemSet::new()
+                    // Every gameplay system reads the clock, so it is updated before all of them
+                    .with_run_criteria(is_game_state_criteria)
+                    .with_system(song_clock_system),
+            )
             .add_audio_channel::<MusicChannel>();
     }
 }
 
 // region Beatmap Resources
 #[derive(Default)]
-pub struct Beatmap(pub HashMap<String, OsuFileSection>);
+pub struct Beatmap(pub OsuBeatmap);
 
 #[derive(Default)]
 pub struct BeatmapPlayback {
-    pub start_timer: Timer,
-    pub play_timer: Timer,
-    pub music_offset_timer: Timer,
-    pub beatmap_started: bool,
+    /// Every hit object before this one has been spawned
     pub current_hit_object_id: usize,
+    /// Hit objects after current_hit_object_id that were spawned early, because their fruits rise for longer
+    pub spawned_ahead: HashSet<usize>,
+}
+
+/// The position in the song, which every gameplay system schedules against.
+/// It follows the playback position of the MusicChannel, smoothed between audio updates
+pub struct SongClock {
+    /// Position in the song in milliseconds.
+    /// Negative during the lead-in, before the music starts
+    pub position: f64,
+    /// The instance of the song playing in the MusicChannel, once it has started
+    pub instance: Option<Handle<AudioInstance>>,
+    /// The last position reported by the audio backend, in milliseconds.
+    /// It only changes when the audio thread reports back, which is less often than every frame
+    last_audio_position: Option<f64>,
+}
+
+impl Default for SongClock {
+    fn default() -> Self {
+        SongClock {
+            // Fruits spawn before they have to be cut (about BEATMAP_MUSIC_OFFSET_TIME for a
+            // fruit cut at the top of the screen), so a beatmap starting at 0 needs some lead-in
+            position: -((BEATMAP_INITIAL_WAIT_TIME + BEATMAP_MUSIC_OFFSET_TIME) * 1000.) as f64,
+            instance: None,
+            last_audio_position: None,
+        }
+    }
+}
+
+impl SongClock {
+    /// Go back to the start of the lead-in. The music has to be stopped separately
+    pub fn reset(&mut self) {
+        *self = SongClock::default();
+    }
+
+    pub fn music_started(&self) -> bool {
+        self.instance.is_some()
+    }
 }
 
 struct BeatMapSong( Handle<bevy_kira_audio::AudioSource> );
@@ -44,109 +103,233 @@ pub struct MusicChannel;
 #[derive(Component)]
 pub struct BackgroundSprite;
 
+/// Inserted when the beatmap could not be loaded.
+/// The loading screen shows it and waits for the player before going back to the song select
+pub enum BeatmapLoadError {
+    Beatmap(OsuError),
+    /// The audio file of the beatmap doesn't exist
+    MissingAudio(String),
+    /// The audio file is in a format that can't be played
+    UnsupportedAudio(String),
+    /// Files the AssetServer failed to load, found by the loading screen
+    FailedAssets(Vec<String>),
+}
+
+impl fmt::Display for BeatmapLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BeatmapLoadError::Beatmap(error) => write!(f, "{error}"),
+            BeatmapLoadError::MissingAudio(file) => write!(f, "The audio file {file} is missing"),
+            BeatmapLoadError::UnsupportedAudio(file) => write!(
+                f,
+                "The audio file {file} is in an unsupported format (supported: {})",
+                SUPPORTED_AUDIO_EXTENSIONS.join(", ")
+            ),
+            BeatmapLoadError::FailedAssets(files) => {
+                write!(f, "These files could not be loaded: {}", files.join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Component)]
+struct LoadErrorText;
+
 // endregion
-fn beatmap_start_system(
-    mut beatmap_playback: ResMut<BeatmapPlayback>,
-    music: Res<BeatMapSong>,
+fn song_clock_system(
+    mut song_clock: ResMut<SongClock>,
+    music: Option<Res<BeatMapSong>>,
     time: Res<Time>,
-    beatmap: ResMut<Beatmap>,
     music_channel: Res<AudioChannel<MusicChannel>>,
+    audio_instances: Res<Assets<AudioInstance>>,
+    mods: Res<Mods>,
+    audio_settings: Res<AudioSettings>,
 ) {
-    // If the timer for starting the fruit spawning has just finished
-    if beatmap_playback
-        .start_timer
-        .tick(time.delta())
-        .just_finished()
-    {
-        // Set the playback to start spawning fruits
-        beatmap_playback.beatmap_started = true;
-        beatmap_playback.play_timer.unpause();
-        // Music should start a short duration after the fruits start spawning
-        beatmap_playback.music_offset_timer.unpause();
+    // Advance with the frame time, so the clock moves smoothly between audio updates
+    // (and during the lead-in, when there is no audio yet).
+    // Double Time and Half Time speed up the clock and the music alike
+    song_clock.position += time.delta_seconds_f64() * 1000. * mods.playback_rate();
+
+    // The clock runs global_offset behind the music
+    let offset = audio_settings.global_offset;
+
+    // Start the music once the lead-in is over
+    if !song_clock.music_started() && song_clock.position >= -offset {
+        if let Some(music) = music {
+            song_clock.instance = Some(
+                music_channel
+                    .play(music.0.clone())
+                    .with_playback_rate(mods.playback_rate())
+                    .handle(),
+            );
+        }
     }
 
-    // If the timer for starting the music has just finished
-    if beatmap_playback
-        .music_offset_timer
-        .tick(time.delta())
-        .just_finished()
+    let audio_position = match song_clock
+        .instance
+        .as_ref()
+        .and_then(|instance| audio_instances.get(instance))
+        .map(|instance| instance.state())
     {
+        Some(PlaybackState::Playing { position }) => position * 1000. - offset,
+        _ => return, // Not playing yet (or anymore), keep following the frame time
+    };
 
-        // Play this 'music' asset in the MusicChannel
-        music_channel.play(music.0.clone());
+    // Only correct the clock when the audio thread reports a new position
+    if song_clock.last_audio_position == Some(audio_position) {
+        return;
     }
-}
+    song_clock.last_audio_position = Some(audio_position);
 
-fn init_system(mut commands: Commands, asset_server: Res<AssetServer>, mut sections_loaded: ResMut<SectionsLoaded>) {
-    // Request a restart at the start of the game
-    let path = "assets/beatmaps/".to_string() + BEATMAP_FILE_NAME;
-    let beatmap = Beatmap(osu_reader::open_osu(&path));
-    let mut beatmap_playback = BeatmapPlayback::default();
-
-    // Get the HitObjects list
-    if let OsuFileSection::HitObjects(hit_objects) = beatmap.0.get("[HitObjects]").unwrap() {
-        // Initialize the timer for the entire beat playing (automatically ends after last HitObject)
-        beatmap_playback.play_timer = Timer::new(
-            Duration::from_millis(hit_objects.last().map_or(0, |hit_obj| hit_obj.time.into())),
-            false,
-        );
-
-        // Initialize the timers (probably will want to adjust these timings based on the beatmaps/settings)
-        beatmap_playback.start_timer = Timer::from_seconds(BEATMAP_INITIAL_WAIT_TIME, false);
-        beatmap_playback.music_offset_timer = Timer::from_seconds(BEATMAP_MUSIC_OFFSET_TIME, false);
+    let drift = audio_position - song_clock.position;
+    if drift.abs() > SONG_CLOCK_MAX_DRIFT as f64 {
+        // Too far off (e.g. after a frame hitch), jump to the audio position
+        song_clock.position = audio_position;
+    } else {
+        // Ease towards the audio position, so fruits don't jitter
+        song_clock.position += drift * SONG_CLOCK_CORRECTION as f64;
     }
+}
 
-    if let OsuFileSection::Events(background) = beatmap.0.get("[Events]").unwrap() {
-        let path = "beatmaps/".to_string() + background;
+fn init_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    selected: Res<SelectedBeatmap>,
+    mut loading_assets: ResMut<LoadingAssets>,
+) {
+    let beatmap = match osu_reader::open_osu(&selected.0.path().to_string_lossy()) {
+        Ok(beatmap) => Beatmap(beatmap),
+        Err(error) => {
+            error!("Could not load the beatmap: {error}");
+            // The loading screen waits until the player has seen the error
+            commands.insert_resource(BeatmapLoadError::Beatmap(error));
+            return;
+        }
+    };
+    if let Some(background) = &beatmap.0.events.background {
+        let path = selected.0.asset_path(background);
 
-        // Spawn background (starts off invisible, becomes visible when the image is loaded)
+        // Spawn background, the loading screen waits for its image
+        let texture = asset_server.load(&path);
+        loading_assets.track(&texture);
         commands
             .spawn_bundle(SpriteBundle {
                 transform: Transform::from_xyz(0., 0., -1.),
-                visibility: Visibility { is_visible: false },
-                texture: asset_server.load(&path).clone(),
+                texture,
                 sprite: Sprite {
                     color: Color::DARK_GRAY,
                     ..Default::default()
                 },
                 ..Default::default()
             })
-            .insert(BackgroundSprite);
+            .insert(BackgroundSprite)
+            .insert(GameEntity);
     }
 
-    // Get song
-    let mut audio_filename = &String::new();
-    if let OsuFileSection::KeyValueMap(section_data) = beatmap.0.get("[General]").unwrap() {
-        audio_filename = section_data.get("AudioFilename").unwrap();
-    }
+    // Get song (some beatmaps have none)
+    let audio_filename = &beatmap.0.general.audio_filename;
+    if !audio_filename.is_empty() {
+        let audio_path = selected.0.directory.join(audio_filename.replace('\\', "/"));
+        if let Err(error) = check_audio_file(&audio_path) {
+            error!("Could not load the beatmap: {error}");
+            commands.insert_resource(error);
+            return;
+        }
 
-    // TODO: get rid of temporary forced conversion and actually accept .mp3 files
-    let audio_filename = audio_filename.replace(".mp3", ".ogg");
-    let music = asset_server.load(&("beatmaps/".to_string() + &audio_filename));
-    
-    commands.insert_resource(BeatMapSong(music));
+        let music = asset_server.load(&selected.0.asset_path(audio_filename));
+        loading_assets.track(&music);
+        commands.insert_resource(BeatMapSong(music));
+    } else {
+        // Don't play the song of the previous beatmap
+        commands.remove_resource::<BeatMapSong>();
+    }
 
     commands.insert_resource(beatmap);
-    commands.insert_resource(beatmap_playback);
+    commands.insert_resource(BeatmapPlayback::default());
+    commands.insert_resource(SongClock::default());
+}
+
+/// Fail early with a clear message instead of letting the audio decoder fail silently
+fn check_audio_file(path: &Path) -> Result<(), BeatmapLoadError> {
+    let file = path.to_string_lossy().to_string();
 
-    sections_loaded.0 += 1;
+    if !path.exists() {
+        return Err(BeatmapLoadError::MissingAudio(file));
+    }
+
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    match extension {
+        Some(extension) if SUPPORTED_AUDIO_EXTENSIONS.contains(&extension.as_str()) => Ok(()),
+        _ => Err(BeatmapLoadError::UnsupportedAudio(file)),
+    }
+}
+
+fn load_error_screen_system(
+    mut commands: Commands,
+    load_error: Option<Res<BeatmapLoadError>>,
+    fonts: Option<Res<FontHandles>>,
+    keyboard: Res<Input<KeyCode>>,
+    mut game_state: ResMut<State<GameStates>>,
+    query: Query<Entity, With<LoadErrorText>>,
+    game_entities: Query<Entity, With<GameEntity>>,
+) {
+    let (load_error, fonts) = match (load_error, fonts) {
+        (Some(load_error), Some(fonts)) => (load_error, fonts),
+        _ => return,
+    };
+
+    // Show the error
+    if query.is_empty() {
+        commands
+            .spawn_bundle(
+                TextBundle::from_section(
+                    format!(
+                        "Could not load the beatmap\n{}\n\nPress any key to go back",
+                        *load_error
+                    ),
+                    TextStyle {
+                        font: fonts.rubik_regular.clone(),
+                        font_size: 24.0,
+                        color: Color::WHITE,
+                    },
+                )
+                .with_style(Style {
+                    margin: UiRect::all(Val::Px(20.)),
+                    ..default()
+                }),
+            )
+            .insert(LoadErrorText);
+        return;
+    }
+
+    // Let the player pick another beatmap once they acknowledge it
+    if keyboard.get_just_pressed().next().is_some() {
+        commands.remove_resource::<BeatmapLoadError>();
+        // The game is never entered, so what was spawned for it is despawned here
+        game_entities.for_each(|entity| commands.entity(entity).despawn_recursive());
+        game_state.overwrite_set(GameStates::SongSelect).unwrap();
+    }
 }
 
 fn background_scaling_system(
     window: Res<Windows>,
-    images: ResMut<Assets<Image>>,
-    mut query: Query<(&Handle<Image>, &mut Sprite, &mut Visibility), With<BackgroundSprite>>,
+    images: Res<Assets<Image>>,
+    mut query: Query<(&Handle<Image>, &mut Sprite), With<BackgroundSprite>>,
 ) {
-    let (handle, mut sprite, mut visibility) = query.single_mut();
+    // Not every beatmap has a background
+    let (handle, mut sprite) = match query.get_single_mut() {
+        Ok(background) => background,
+        Err(_) => return,
+    };
 
-    // If the asset for the Background image has been loaded
-    if let Some(image) = images.get(&handle) {
+    // Loaded before the game starts, but the loading screen shows it as soon as it is there
+    if let Some(image) = images.get(handle) {
         let window = window.get_primary().unwrap();
         // Set the size of the background to cover the entire screen
         sprite.custom_size = Some(
             image.size() * (window.width() / image.size().x).max(window.height() / image.size().y),
         );
-
-        visibility.is_visible = true;
     }
 }
@@ -25,6 +25,11 @@ pub struct MainCamera;
 
 #[derive(Component)]
 pub struct Aim;
+
+/// Everything that belongs to a run of a beatmap, from the loading screen to the end of the game.
+/// Despawned when leaving the game, without touching what other states spawned
+#[derive(Component)]
+pub struct GameEntity;
 //endregion
 
 #[derive(Component)]
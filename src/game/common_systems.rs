@@ -1,14 +1,30 @@
-use crate::game::{common_components::{GravityAffects, TimeAnimation, Velocity}, controls::{Dash, Movement}, fruit_plugin::{Fruit, FruitPart}, player_plugin::Player, Score, PLAYER_SIZE, is_game_state_criteria};
+use crate::game::{common_components::{GravityAffects, TimeAnimation, Velocity}, controls::{Dash, Movement}, fruit_plugin::{Fruit, FruitPart}, player_plugin::Player, scoring::ScoreState, GameRng, PLAYER_SIZE, is_game_state_criteria};
+use crate::game::{MAX_PHYSICS_STEPS_PER_FRAME, PHYSICS_STEP_SCALE, PHYSICS_TIMESTEP};
+use crate::game::controls::PlayerInput;
+use crate::game::mods::Mods;
+use crate::GameStates;
+use bevy::ecs::schedule::ShouldRun;
 use bevy::prelude::*;
+use bevy::transform::TransformSystem;
 use bevy_kira_audio::{AudioChannel, AudioControl};
 
-use super::beatmap_plugin::{BeatmapPlayback, MusicChannel};
+use super::beatmap_plugin::{BeatmapPlayback, MusicChannel, SongClock};
 
 pub struct CommonSystems;
 
 impl Plugin for CommonSystems {
     fn build(&self, app: &mut App) {
         app
+            .insert_resource(PhysicsClock::default())
+            // Runs fixed steps until the simulation catches up with the song clock, so the simulation
+            // doesn't depend on the frame rate. Within a step the systems are ordered by these labels:
+            // "step_start" -> "input" -> "dash" -> "player_movement" -> "gravity_strength" -> "gravity"
+            // -> "move" -> "corners" -> "collisions" -> "cuts" -> "reach_bottom" -> spawning and "health"
+            .add_stage_after(
+                CoreStage::PreUpdate,
+                PhysicsStage,
+                SystemStage::parallel().with_run_criteria(physics_step_criteria),
+            )
 
             .add_system_set_to_stage (
                 CoreStage::PreUpdate,
@@ -17,15 +33,178 @@ impl Plugin for CommonSystems {
                     .with_run_criteria(is_game_state_criteria)
 
                     // The actual systems
-                    .with_system(move_with_velocity_system)
-                    .with_system(gravity_system)
                     .with_system(process_time_animations)
-                    .with_system(restart_game_system),
+                    .with_system(restart_game_system)
+                    .with_system(restore_simulated_translation_system.after(restart_game_system)),
+            )
+            .add_system_set_to_stage(
+                PhysicsStage,
+                SystemSet::new()
+                    .with_system(advance_physics_clock_system.label("step_start"))
+                    .with_system(record_previous_translation_system.label("step_start"))
+                    .with_system(gravity_system.label("gravity").after("player_movement").after("gravity_strength"))
+                    .with_system(move_with_velocity_system.label("move").after("gravity")),
+            )
+            .add_system_set_to_stage(
+                CoreStage::PostUpdate,
+                SystemSet::new()
+                    .with_run_criteria(is_game_state_criteria)
+                    .with_system(interpolate_translation_system.before(TransformSystem::TransformPropagate)),
             )
         .add_event::<RestartEvent>();
     }
 }
 
+//region Fixed Timestep
+/// Runs the physics of the game, PHYSICS_TIMESTEP at a time, between the input in PreUpdate and Update
+#[derive(Debug, Clone, PartialEq, Eq, Hash, StageLabel)]
+pub struct PhysicsStage;
+
+/// Where the simulation is in the song. It only moves by whole steps, so what the steps read from it
+/// (spawn times, cut judgements) is the same however the frames fall
+pub struct PhysicsClock {
+    /// Song position of the current step, in milliseconds
+    pub position: f64,
+    start: f64,
+    steps: u64,
+    steps_this_frame: u32,
+    stepping: bool, // Whether the stage already stepped this frame
+}
+
+impl Default for PhysicsClock {
+    fn default() -> Self {
+        let start = SongClock::default().position;
+        PhysicsClock {
+            position: start,
+            start,
+            steps: 0,
+            steps_this_frame: 0,
+            stepping: false,
+        }
+    }
+}
+
+impl PhysicsClock {
+    /// How long a step is in song milliseconds. Double Time and Half Time change how much
+    /// of the song a step covers, not how long it is
+    pub fn step_millis(playback_rate: f64) -> f64 {
+        PHYSICS_TIMESTEP * 1000. * playback_rate
+    }
+
+    /// Position after `steps` steps, multiplied instead of summed so no error builds up
+    fn position_at(&self, steps: u64, playback_rate: f64) -> f64 {
+        self.start + steps as f64 * Self::step_millis(playback_rate)
+    }
+
+    /// How far the song clock is between the current step and the next one, from 0 to 1
+    pub fn alpha(&self, song_position: f64, playback_rate: f64) -> f32 {
+        ((song_position - self.position) / Self::step_millis(playback_rate)).clamp(0., 1.) as f32
+    }
+
+    fn reset(&mut self) {
+        *self = PhysicsClock::default();
+    }
+}
+
+fn physics_step_criteria(
+    game_state: Res<State<GameStates>>,
+    song_clock: Option<Res<SongClock>>,
+    mods: Res<Mods>,
+    mut clock: ResMut<PhysicsClock>,
+) -> ShouldRun {
+    // Nothing is simulated outside of the game. The song clock stops while paused, so nothing is made up for
+    let song_clock = match song_clock {
+        Some(song_clock) if matches!(game_state.current(), GameStates::Game) => song_clock,
+        _ => {
+            clock.stepping = false;
+            return ShouldRun::No;
+        }
+    };
+
+    // This is checked again after every step
+    if !clock.stepping {
+        clock.steps_this_frame = 0;
+    }
+
+    // After a long frame, the steps left are simulated over the next frames instead of dropped
+    let next_position = clock.position_at(clock.steps + 1, mods.playback_rate());
+    if clock.steps_this_frame < MAX_PHYSICS_STEPS_PER_FRAME && next_position <= song_clock.position {
+        clock.steps_this_frame += 1;
+        clock.stepping = true;
+        ShouldRun::YesAndCheckAgain
+    } else {
+        clock.stepping = false;
+        ShouldRun::No
+    }
+}
+
+fn advance_physics_clock_system(mut clock: ResMut<PhysicsClock>, mods: Res<Mods>) {
+    clock.steps += 1;
+    clock.position = clock.position_at(clock.steps, mods.playback_rate());
+}
+
+/// Where a moving entity was after the last two steps. It is drawn in between them,
+/// so the movement looks smooth on monitors faster than the physics
+#[derive(Component)]
+pub struct RenderInterpolation {
+    previous: Vec3,
+    current: Vec3,
+    rendered: Vec3, // What the Transform was set to, to tell when something else moved the entity
+}
+
+impl RenderInterpolation {
+    fn at(translation: Vec3) -> Self {
+        RenderInterpolation {
+            previous: translation,
+            current: translation,
+            rendered: translation,
+        }
+    }
+}
+
+/// Puts the entities back where the physics left them before the next steps
+fn restore_simulated_translation_system(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut Transform, Option<&mut RenderInterpolation>), With<Velocity>>,
+) {
+    for (entity, mut tf, interpolation) in query.iter_mut() {
+        match interpolation {
+            None => {
+                commands.entity(entity).insert(RenderInterpolation::at(tf.translation));
+            }
+            Some(mut interpolation) => {
+                if tf.translation == interpolation.rendered {
+                    tf.translation = interpolation.current;
+                } else {
+                    // Moved outside of the physics (e.g. restarting), so it teleports instead of sliding there
+                    *interpolation = RenderInterpolation::at(tf.translation);
+                }
+            }
+        }
+    }
+}
+
+fn record_previous_translation_system(mut query: Query<(&Transform, &mut RenderInterpolation)>) {
+    for (tf, mut interpolation) in query.iter_mut() {
+        interpolation.previous = tf.translation;
+    }
+}
+
+fn interpolate_translation_system(
+    mut query: Query<(&mut Transform, &mut RenderInterpolation)>,
+    clock: Res<PhysicsClock>,
+    song_clock: Res<SongClock>,
+    mods: Res<Mods>,
+) {
+    let alpha = clock.alpha(song_clock.position, mods.playback_rate());
+    for (mut tf, mut interpolation) in query.iter_mut() {
+        interpolation.current = tf.translation;
+        tf.translation = interpolation.previous.lerp(interpolation.current, alpha);
+        interpolation.rendered = tf.translation;
+    }
+}
+//endregion
+
 fn process_time_animations(
     mut query: Query<(&mut Transform, &mut TimeAnimation)>,
     time: Res<Time>,
@@ -36,22 +215,21 @@ fn process_time_animations(
     });
 }
 
-fn move_with_velocity_system(mut query: Query<(&mut Transform, &Velocity)>, time: Res<Time>) {
+fn move_with_velocity_system(mut query: Query<(&mut Transform, &Velocity)>) {
     for (mut tf, vl) in query.iter_mut() {
         let mut translation: &mut Vec3 = &mut tf.translation;
 
-        // Multiply by 60 and time delta in order to
-        // Make the game independent of frames
+        // Velocities are per 1/60th of a second
         // (My monitor is 60hz so that's the default)
-        translation.x += vl.x * 60. * time.delta_seconds();
-        translation.y += vl.y * 60. * time.delta_seconds();
+        translation.x += vl.x * PHYSICS_STEP_SCALE;
+        translation.y += vl.y * PHYSICS_STEP_SCALE;
     }
 }
 
-fn gravity_system(mut query: Query<(&mut Velocity, &GravityAffects)>, time: Res<Time>) {
+fn gravity_system(mut query: Query<(&mut Velocity, &GravityAffects)>) {
     for (mut vl, ga) in query.iter_mut() {
         // Acceleration = ms^-2
-        vl.y -= ga.strength * 60. * time.delta_seconds();
+        vl.y -= ga.strength * PHYSICS_STEP_SCALE;
     }
 }
 
@@ -64,13 +242,17 @@ pub enum RestartEvent {
 
 fn restart_game_system(
     mut query: Query<(&mut Transform, &mut Velocity), With<Player>>,
-    mut score: ResMut<Score>,
+    mut score: ResMut<ScoreState>,
     mut commands: Commands,
     despawn_fruit_query: Query<Entity, Or<(With<Fruit>, With<FruitPart>)>>,
     mut movement: ResMut<Movement>,
     mut dash: ResMut<Dash>,
     mut beatmap_playback: ResMut<BeatmapPlayback>,
+    mut song_clock: ResMut<SongClock>,
     music_channel: Res<AudioChannel<MusicChannel>>,
+    mut game_rng: ResMut<GameRng>,
+    mut physics_clock: ResMut<PhysicsClock>,
+    mut input: ResMut<PlayerInput>,
     window: Res<Windows>,
     mut restart_events: EventReader<RestartEvent>,
 ) {
@@ -97,22 +279,23 @@ fn restart_game_system(
             dash.is_dashing = false;
             dash.trying_to_dash = false;
             dash.direction = Vec2::ZERO;
+            input.clear_presses();
             
             if let RestartEvent::OnlyPlayer = event {
+                // Falling off the stage loses the combo, but not the run
+                score.break_combo();
                 return;
             }
             
-            score.0 = 0;
+            score.reset();
             
             music_channel.stop();
             
-            beatmap_playback.current_hit_object_id = 0;
-            beatmap_playback.play_timer.reset();
-            beatmap_playback.play_timer.pause();
-            beatmap_playback.music_offset_timer.reset();
-            beatmap_playback.music_offset_timer.pause();
-            beatmap_playback.start_timer.reset();
-            beatmap_playback.beatmap_started = false;
+            *beatmap_playback = BeatmapPlayback::default();
+            song_clock.reset();
+            // Replaying the same inputs plays out the same way
+            game_rng.reset();
+            physics_clock.reset();
             // TODO: wait does this really not work?
             // *movement.as_mut() = Movement::default();
             // *dash.as_mut() = Dash::default();
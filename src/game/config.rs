@@ -0,0 +1,347 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use bevy::window::WindowMode;
+use bevy_kira_audio::{AudioChannel, AudioControl};
+
+use super::beatmap_plugin::MusicChannel;
+use super::key_bindings::{
+    gamepad_button_from_name, gamepad_button_names, key_code_from_name, Action, GamepadBindings,
+    KeyBindings,
+};
+use super::{user_dirs, AudioSettings, DashScheme, GameSettings};
+
+/// The version written by this build. Older files are upgraded with RENAMED_KEYS when read
+const CONFIG_VERSION: u32 = 2;
+const CONFIG_FILE_NAME: &str = "config.txt";
+
+/// Keys that were renamed: (the version that renamed it, old name, new name)
+const RENAMED_KEYS: &[(u32, &str, &str)] = &[
+    // v2 bound keys to actions
+    (2, "key_jump", "bind_jump"),
+    (2, "key_fast_fall", "bind_fast_fall"),
+    (2, "key_left", "bind_move_left"),
+    (2, "key_right", "bind_move_right"),
+    (2, "key_dash_up", "bind_dash_up"),
+    (2, "key_dash_down", "bind_dash_down"),
+    (2, "key_dash_left", "bind_dash_left"),
+    (2, "key_dash_right", "bind_dash_right"),
+];
+
+//region Plugin Boilerplate
+/// Loads the preferences saved in the Config (which main reads before the window opens)
+/// and saves them whenever they change
+pub struct ConfigPlugin;
+
+impl Plugin for ConfigPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(apply_config_system)
+            .add_system(save_config_system)
+            .add_system(apply_volume_system)
+            .add_system(toggle_fullscreen_system);
+    }
+}
+//endregion
+
+//region Config
+/// Everything saved in the config file.
+/// As a resource, it is what was last read from or written to the file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub game_settings: GameSettings,
+    pub audio: AudioSettings,
+    pub key_bindings: KeyBindings,
+    pub gamepad_bindings: GamepadBindings,
+    pub window_mode: WindowMode,
+    /// Lines this version doesn't know about, written back as they were so newer versions keep them
+    unknown: Vec<(String, String)>,
+    /// Problems found while reading the file. It is read before the logger exists, so they are logged on startup
+    warnings: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            game_settings: GameSettings::default(),
+            audio: AudioSettings::default(),
+            key_bindings: KeyBindings::default(),
+            gamepad_bindings: GamepadBindings::default(),
+            window_mode: WindowMode::Windowed,
+            unknown: vec![],
+            warnings: vec![],
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> PathBuf {
+        user_dirs::config_dir().join(CONFIG_FILE_NAME)
+    }
+
+    /// Read the config file. Anything missing or invalid keeps its default
+    pub fn load() -> Self {
+        match fs::read_to_string(Config::path()) {
+            Ok(contents) => Config::parse(&contents),
+            Err(error) => {
+                let mut config = Config::default();
+                if error.kind() != io::ErrorKind::NotFound {
+                    config
+                        .warnings
+                        .push(format!("Could not read {}: {error}", Config::path().display()));
+                }
+                config
+            }
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        user_dirs::write_atomically(&Config::path(), &self.to_string())
+    }
+
+    /// Read `key = value` lines. Lines starting with # are comments
+    pub fn parse(contents: &str) -> Self {
+        let mut entries: Vec<(String, String)> = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+            .collect();
+
+        // Files from before the version key are version 1
+        let version = entries
+            .iter()
+            .find(|(key, _)| key == "version")
+            .and_then(|(_, value)| value.parse().ok())
+            .unwrap_or(1);
+        let mut config = Config::default();
+        if version > CONFIG_VERSION {
+            config.warnings.push(
+                "The config file is from a newer version of the game, some settings may be ignored".to_string(),
+            );
+        }
+        migrate(&mut entries, version);
+
+        for (key, value) in entries {
+            if key == "version" {
+                continue;
+            }
+
+            match config.set(&key, &value) {
+                Ok(true) => {}
+                Ok(false) => config.unknown.push((key, value)),
+                Err(()) => config
+                    .warnings
+                    .push(format!("Invalid value for {key} in the config file: {value}")),
+            }
+        }
+        config
+    }
+
+    /// Set the value of a key. Ok(false) if the key is unknown, Err if the value is invalid
+    fn set(&mut self, key: &str, value: &str) -> Result<bool, ()> {
+        let number = || value.parse::<f64>().map_err(|_| ());
+        let boolean = || value.parse::<bool>().map_err(|_| ());
+
+        match key {
+            "dash_stop" => self.game_settings.dash_stop = boolean()?,
+            "snap_on_cut" => self.game_settings.snap_on_cut = boolean()?,
+            "dash_scheme" => self.game_settings.dash_scheme = dash_scheme_from_name(value).ok_or(())?,
+            "master_volume" => self.audio.master_volume = number()?.clamp(0., 1.),
+            "music_volume" => self.audio.music_volume = number()?.clamp(0., 1.),
+            "global_offset" => self.audio.global_offset = number()?,
+            "window_mode" => self.window_mode = window_mode_from_name(value).ok_or(())?,
+            "gamepad_deadzone" => self.gamepad_bindings.deadzone = number()?.clamp(0., 0.9) as f32,
+            "pad_dash" => self.gamepad_bindings.dash = parse_gamepad_buttons(value)?,
+            _ => {
+                if let Some(action) = key.strip_prefix("bind_").and_then(Action::from_id) {
+                    self.key_bindings.set_keys(action, parse_keys(value)?);
+                } else if let Some(action) = key.strip_prefix("pad_").and_then(Action::from_id) {
+                    self.gamepad_bindings
+                        .set_buttons(action, parse_gamepad_buttons(value)?);
+                } else {
+                    return Ok(false);
+                }
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+impl std::fmt::Display for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "# Need 4 Fruits settings")?;
+        writeln!(f, "version = {CONFIG_VERSION}")?;
+        writeln!(f)?;
+        writeln!(f, "# Gameplay")?;
+        writeln!(f, "dash_stop = {}", self.game_settings.dash_stop)?;
+        writeln!(f, "snap_on_cut = {}", self.game_settings.snap_on_cut)?;
+        writeln!(f, "# Arrows (dash keys) or Mouse (click to dash toward the cursor)")?;
+        writeln!(f, "dash_scheme = {:?}", self.game_settings.dash_scheme)?;
+        writeln!(f)?;
+        writeln!(f, "# Audio (volumes from 0 to 1, offset in milliseconds)")?;
+        writeln!(f, "master_volume = {}", self.audio.master_volume)?;
+        writeln!(f, "music_volume = {}", self.audio.music_volume)?;
+        writeln!(f, "global_offset = {}", self.audio.global_offset)?;
+        writeln!(f)?;
+        writeln!(f, "# Windowed, BorderlessFullscreen or Fullscreen")?;
+        writeln!(f, "window_mode = {:?}", self.window_mode)?;
+        writeln!(f)?;
+        writeln!(f, "# Keys, separated by commas")?;
+        for action in Action::ALL {
+            writeln!(f, "bind_{} = {}", action.id(), self.key_bindings.key_names(action))?;
+        }
+        writeln!(f)?;
+        writeln!(f, "# Gamepad buttons, separated by commas. pad_dash dashes toward the left stick")?;
+        writeln!(f, "gamepad_deadzone = {}", self.gamepad_bindings.deadzone)?;
+        writeln!(f, "pad_dash = {}", gamepad_button_names(&self.gamepad_bindings.dash))?;
+        for action in Action::ALL {
+            writeln!(f, "pad_{} = {}", action.id(), self.gamepad_bindings.button_names(action))?;
+        }
+
+        if !self.unknown.is_empty() {
+            writeln!(f)?;
+            for (key, value) in &self.unknown {
+                writeln!(f, "{key} = {value}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn migrate(entries: &mut [(String, String)], version: u32) {
+    for (renamed_in, old_name, new_name) in RENAMED_KEYS {
+        if version >= *renamed_in {
+            continue;
+        }
+
+        for (key, _) in entries.iter_mut() {
+            if *key == *old_name {
+                *key = new_name.to_string();
+            }
+        }
+    }
+}
+//endregion
+
+//region Key Names
+fn parse_keys(value: &str) -> Result<Vec<KeyCode>, ()> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| key_code_from_name(name).ok_or(()))
+        .collect()
+}
+
+fn parse_gamepad_buttons(value: &str) -> Result<Vec<GamepadButtonType>, ()> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| gamepad_button_from_name(name).ok_or(()))
+        .collect()
+}
+
+fn dash_scheme_from_name(name: &str) -> Option<DashScheme> {
+    match name {
+        "Arrows" => Some(DashScheme::Arrows),
+        "Mouse" => Some(DashScheme::Mouse),
+        _ => None,
+    }
+}
+
+fn window_mode_from_name(name: &str) -> Option<WindowMode> {
+    match name {
+        "Windowed" => Some(WindowMode::Windowed),
+        "BorderlessFullscreen" => Some(WindowMode::BorderlessFullscreen),
+        "SizedFullscreen" => Some(WindowMode::SizedFullscreen),
+        "Fullscreen" => Some(WindowMode::Fullscreen),
+        _ => None,
+    }
+}
+//endregion
+
+/// Split the config into the resources the game reads
+fn apply_config_system(mut commands: Commands, mut config: ResMut<Config>) {
+    for warning in config.warnings.drain(..) {
+        warn!("{warning}");
+    }
+
+    commands.insert_resource(config.game_settings.clone());
+    commands.insert_resource(config.audio.clone());
+    commands.insert_resource(config.key_bindings.clone());
+    commands.insert_resource(config.gamepad_bindings.clone());
+}
+
+/// Save the config when any of the preferences changed
+fn save_config_system(
+    mut config: ResMut<Config>,
+    game_settings: Option<Res<GameSettings>>,
+    audio: Option<Res<AudioSettings>>,
+    key_bindings: Option<Res<KeyBindings>>,
+    gamepad_bindings: Option<Res<GamepadBindings>>,
+    windows: Res<Windows>,
+) {
+    // The resources are inserted by apply_config_system's commands, after the first frame starts
+    let (game_settings, audio, key_bindings, gamepad_bindings) =
+        match (game_settings, audio, key_bindings, gamepad_bindings) {
+            (Some(game_settings), Some(audio), Some(key_bindings), Some(gamepad_bindings)) => {
+                (game_settings, audio, key_bindings, gamepad_bindings)
+            }
+            _ => return,
+        };
+
+    let window_mode = windows
+        .get_primary()
+        .map_or(config.window_mode, |window| window.mode());
+    let changed = game_settings.is_changed()
+        || audio.is_changed()
+        || key_bindings.is_changed()
+        || gamepad_bindings.is_changed()
+        || window_mode != config.window_mode;
+    if !changed {
+        return;
+    }
+
+    let current = Config {
+        game_settings: game_settings.clone(),
+        audio: audio.clone(),
+        key_bindings: key_bindings.clone(),
+        gamepad_bindings: gamepad_bindings.clone(),
+        window_mode,
+        unknown: config.unknown.clone(),
+        warnings: vec![],
+    };
+
+    if current != *config {
+        *config = current;
+        if let Err(error) = config.save() {
+            error!("Could not save the config to {}: {error}", Config::path().display());
+        }
+    }
+}
+
+fn apply_volume_system(audio: Option<Res<AudioSettings>>, music_channel: Res<AudioChannel<MusicChannel>>) {
+    if let Some(audio) = audio {
+        if audio.is_changed() {
+            music_channel.set_volume(audio.master_volume * audio.music_volume);
+        }
+    }
+}
+
+fn toggle_fullscreen_system(keyboard: Res<Input<KeyCode>>, mut windows: ResMut<Windows>) {
+    if !keyboard.just_pressed(KeyCode::F11) {
+        return;
+    }
+
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_mode(match window.mode() {
+            WindowMode::Windowed => WindowMode::BorderlessFullscreen,
+            _ => WindowMode::Windowed,
+        });
+    }
+}
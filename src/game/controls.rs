@@ -1,7 +1,15 @@
-use crate::game::common_components::Aim;
-use crate::game::{KeyboardControls, MainCamera, TexturesHandles, AIM_SCALE, is_game_state_criteria};
+use crate::game::common_components::{Aim, GameEntity};
+use crate::game::common_systems::{PhysicsStage, RestartEvent};
+use crate::game::key_bindings::{Action, GamepadBindings, KeyBindings};
+use crate::game::player_plugin::Player;
+use crate::game::{
+    DashScheme, GameSettings, MainCamera, TexturesHandles, AIM_SCALE, GAMEPAD_DASH_THRESHOLD, GAMEPAD_FAST_FALL_THRESHOLD,
+    is_game_state_criteria, PHYSICS_TIMESTEP,
+};
 use bevy::prelude::*;
 use bevy::render::camera::RenderTarget;
+use bevy::ui::UiSystem;
+use std::time::Duration;
 
 //region This resource defines the player's movements, defined by the keyboard/controller/mouse
 #[derive(Debug)]
@@ -13,6 +21,32 @@ pub struct Movement {
     pub lock_x: bool,
 }
 
+/// What the controls read since the last physics step. The controls run every frame, but the
+/// simulation only takes this in once at the start of each step, so the steps see the same input
+/// however the frames fall. Presses are kept until a step uses them, held keys are read again every frame
+#[derive(Debug, Default)]
+pub struct PlayerInput {
+    pub x: f32,
+    pub jump: bool,
+    pub fast_fall: bool,
+    /// Added to the current dash direction, like the dash keys always did
+    pub dash_arrows: Vec2,
+    /// Replaces the dash direction (the gamepad's sticks)
+    pub dash_direction: Option<Vec2>,
+    /// Dash from wherever the player is at that step toward this point (the mouse)
+    pub dash_target: Option<Vec2>,
+}
+
+impl PlayerInput {
+    pub fn clear_presses(&mut self) {
+        self.jump = false;
+        self.fast_fall = false;
+        self.dash_arrows = Vec2::ZERO;
+        self.dash_direction = None;
+        self.dash_target = None;
+    }
+}
+
 pub struct MouseCoordinates {
     pub x: f32,
     pub y: f32,
@@ -54,8 +88,9 @@ impl Default for Dash {
 }
 
 impl Dash {
-    pub fn apply_time(&mut self, time: &Res<Time>) {
-        self.duration.tick(time.delta());
+    /// Advances the dash by one physics step
+    pub fn apply_time(&mut self) {
+        self.duration.tick(Duration::from_secs_f64(PHYSICS_TIMESTEP));
     }
 }
 
@@ -76,6 +111,10 @@ impl Default for MouseCoordinates {
         MouseCoordinates { x: 0.0, y: 0.0 }
     }
 }
+
+/// The gamepad that controls the player. None when no gamepad is connected
+#[derive(Default)]
+pub struct ActiveGamepad(pub Option<Gamepad>);
 //endregion
 
 //region Plugin boilerplate
@@ -84,6 +123,9 @@ pub struct ControlsPlugin;
 impl Plugin for ControlsPlugin {
     fn build(&self, app: &mut App) {
         app
+            .insert_resource(ActiveGamepad::default())
+            // Gamepads can be plugged in at any time, even in the menus
+            .add_system_to_stage(CoreStage::PreUpdate, gamepad_connection_system)
 
             .add_system_set_to_stage(
                 CoreStage::PreUpdate,
@@ -94,43 +136,138 @@ impl Plugin for ControlsPlugin {
                     // The actual systems
                     .with_system(cursor_system)
                     .with_system(keyboard_controls_system)
-                    .with_system(dash_direction_arrows),
+                    .with_system(joystick_control_system.after(keyboard_controls_system))
+                    .with_system(dash_direction_arrows)
+                    .with_system(mouse_dash_system.after(cursor_system).after(UiSystem::Focus))
+                    .with_system(retry_system),
+            )
+            .add_system_set_to_stage(
+                PhysicsStage,
+                SystemSet::new().with_system(apply_player_input_system.label("input").after("step_start")),
             );
     }
 }
 //endregion
 
-fn keyboard_controls_system(keyboard: Res<Input<KeyCode>>, mut movement: ResMut<Movement>) {
-    // You can add whatever controls you want to this list
-    let controls = KeyboardControls {
-        up: vec![KeyCode::W],   // In this case, jump
-        down: vec![KeyCode::S], // In this case, fast fall
-        right: vec![KeyCode::D],
-        left: vec![KeyCode::A],
-    };
-
-    if KeyboardControls::is_just_pressed(&keyboard, &controls.up) {
-        movement.jump = true;
+fn keyboard_controls_system(
+    keyboard: Res<Input<KeyCode>>,
+    mut input: ResMut<PlayerInput>,
+    key_bindings: Res<KeyBindings>,
+) {
+    // The keys are set in the controls menu
+    if key_bindings.just_pressed(&keyboard, Action::Jump) {
+        input.jump = true;
     } // Jump will be turned to false once the value is read
 
-    if KeyboardControls::is_pressed(&keyboard, &controls.down) {
-        movement.is_fast_falling = true;
+    if key_bindings.pressed(&keyboard, Action::FastFall) {
+        input.fast_fall = true;
     } // You cancel fast falling by jumping or dashing
 
     let mut sides = 0.;
-    if KeyboardControls::is_pressed(&keyboard, &controls.right) {
+    if key_bindings.pressed(&keyboard, Action::MoveRight) {
         sides += 1.;
     }
-    if KeyboardControls::is_pressed(&keyboard, &controls.left) {
+    if key_bindings.pressed(&keyboard, Action::MoveLeft) {
         sides -= 1.;
     }
-    movement.x = sides;
+    input.x = sides;
+}
 
-    // dbg!(&movement);
+fn gamepad_connection_system(
+    mut gamepad_events: EventReader<GamepadEvent>,
+    gamepads: Res<Gamepads>,
+    mut active_gamepad: ResMut<ActiveGamepad>,
+) {
+    for event in gamepad_events.iter() {
+        match event.event_type {
+            GamepadEventType::Connected => {
+                if active_gamepad.0.is_none() {
+                    info!("Using gamepad {:?}", event.gamepad);
+                    active_gamepad.0 = Some(event.gamepad);
+                }
+            }
+            GamepadEventType::Disconnected => {
+                if active_gamepad.0 == Some(event.gamepad) {
+                    // Switch to another gamepad if there is one
+                    active_gamepad.0 = gamepads
+                        .iter()
+                        .copied()
+                        .find(|gamepad| *gamepad != event.gamepad);
+                    info!("Gamepad {:?} disconnected", event.gamepad);
+                }
+            }
+            _ => {}
+        }
+    }
 }
 
-fn joystick_control_system() {
-    todo!()
+fn joystick_control_system(
+    active_gamepad: Res<ActiveGamepad>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    gamepad_bindings: Res<GamepadBindings>,
+    mut input: ResMut<PlayerInput>,
+    // Whether the right stick was already flicked, so holding it only dashes once
+    mut right_stick_flicked: Local<bool>,
+) {
+    let gamepad = match active_gamepad.0 {
+        Some(gamepad) => gamepad,
+        None => return,
+    };
+
+    let stick = |x, y| {
+        let axis = |axis_type| axes.get(GamepadAxis::new(gamepad, axis_type)).unwrap_or(0.);
+        apply_deadzone(Vec2::new(axis(x), axis(y)), gamepad_bindings.deadzone)
+    };
+    let left_stick = stick(GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY);
+    let right_stick = stick(GamepadAxisType::RightStickX, GamepadAxisType::RightStickY);
+
+    // Jump and fast fall work like their keys
+    if gamepad_bindings.just_pressed(&buttons, gamepad, Action::Jump) {
+        input.jump = true;
+    }
+    if gamepad_bindings.pressed(&buttons, gamepad, Action::FastFall)
+        || left_stick.y < -GAMEPAD_FAST_FALL_THRESHOLD
+    {
+        input.fast_fall = true;
+    }
+
+    // The D-pad moves at full speed, the left stick in between.
+    // The keyboard is only overridden when the gamepad is being used
+    let mut sides = 0.;
+    if gamepad_bindings.pressed(&buttons, gamepad, Action::MoveRight) {
+        sides += 1.;
+    }
+    if gamepad_bindings.pressed(&buttons, gamepad, Action::MoveLeft) {
+        sides -= 1.;
+    }
+    if sides != 0. {
+        input.x = sides;
+    } else if left_stick.x != 0. {
+        input.x = left_stick.x;
+    }
+
+    // Dash towards where the right stick is flicked...
+    let flicked = right_stick.length() > GAMEPAD_DASH_THRESHOLD;
+    if flicked && !*right_stick_flicked {
+        input.dash_direction = Some(right_stick);
+    }
+    *right_stick_flicked = flicked;
+
+    // ...or towards the left stick with the dash button
+    if gamepad_bindings.dash_just_pressed(&buttons, gamepad) && left_stick != Vec2::ZERO {
+        input.dash_direction = Some(left_stick);
+    }
+}
+
+/// Ignore the stick below the deadzone and rescale the rest, so movement starts at 0 past it
+fn apply_deadzone(stick: Vec2, deadzone: f32) -> Vec2 {
+    let length = stick.length();
+    if length <= deadzone {
+        return Vec2::ZERO;
+    }
+
+    stick / length * ((length - deadzone) / (1. - deadzone)).min(1.)
 }
 
 fn cursor_system(
@@ -170,7 +307,8 @@ fn cursor_system(
                     },
                     ..Default::default()
                 })
-                .insert(Aim);
+                .insert(Aim)
+                .insert(GameEntity);
         }
 
         // get the size of the window
@@ -204,27 +342,94 @@ fn cursor_system(
     }
 }
 
-fn dash_direction_arrows(kb: Res<Input<KeyCode>>, mut dash: ResMut<Dash>) {
-    // You can add whatever controls you want to this list
-    let controls = KeyboardControls {
-        up: vec![KeyCode::Up],
-        down: vec![KeyCode::Down],
-        right: vec![KeyCode::Right],
-        left: vec![KeyCode::Left],
-    };
+fn dash_direction_arrows(
+    kb: Res<Input<KeyCode>>,
+    mut input: ResMut<PlayerInput>,
+    key_bindings: Res<KeyBindings>,
+    game_settings: Res<GameSettings>,
+) {
+    if game_settings.dash_scheme != DashScheme::Arrows {
+        return;
+    }
 
     // Convert whether the input has just been clicked to a number
-    let to_num = |x| KeyboardControls::is_just_pressed(&kb, x) as i32 as f32;
+    let to_num = |action| key_bindings.just_pressed(&kb, action) as i32 as f32;
 
-    // Get inputs
-    let direction = dash.direction
-        + Vec2 {
-            x: to_num(&controls.right) - to_num(&controls.left),
-            y: to_num(&controls.up) - to_num(&controls.down),
-        };
+    // Get inputs, the step adds them to the direction of the dash
+    input.dash_arrows += Vec2 {
+        x: to_num(Action::DashRight) - to_num(Action::DashLeft),
+        y: to_num(Action::DashUp) - to_num(Action::DashDown),
+    };
+}
+
+fn mouse_dash_system(
+    mouse: Res<Input<MouseButton>>,
+    mouse_coordinates: Res<MouseCoordinates>,
+    game_settings: Res<GameSettings>,
+    interactions: Query<&Interaction>,
+    mut input: ResMut<PlayerInput>,
+) {
+    if game_settings.dash_scheme != DashScheme::Mouse || !mouse.just_pressed(MouseButton::Left) {
+        return;
+    }
+
+    // The click belongs to a button (e.g. the Gameplay Options), not to the game
+    if interactions.iter().any(|interaction| *interaction != Interaction::None) {
+        return;
+    }
 
-    if direction != Vec2::ZERO {
+    // The step aims from where the player is simulated, not where it is drawn
+    input.dash_target = Some(Vec2::new(mouse_coordinates.x, mouse_coordinates.y));
+}
+
+/// Take in what the controls read, once at the start of every physics step
+fn apply_player_input_system(
+    mut input: ResMut<PlayerInput>,
+    mut movement: ResMut<Movement>,
+    mut dash: ResMut<Dash>,
+    q_player: Query<&Transform, With<Player>>,
+) {
+    movement.x = input.x;
+    if input.jump {
+        movement.jump = true;
+    }
+    if input.fast_fall {
+        movement.is_fast_falling = true;
+    }
+
+    let mut direction = dash.direction + input.dash_arrows;
+    if let Some(dash_direction) = input.dash_direction {
+        direction = dash_direction;
+    }
+    if let (Some(target), Ok(player)) = (input.dash_target, q_player.get_single()) {
+        // The dash system normalizes it, so the exact angle to the cursor is kept
+        direction = target - player.translation.truncate();
+    }
+
+    let dash_pressed = input.dash_arrows != Vec2::ZERO
+        || input.dash_direction.is_some()
+        || input.dash_target.is_some();
+    if dash_pressed && direction != Vec2::ZERO {
         dash.trying_to_dash = true;
         dash.direction = direction;
     }
+
+    input.clear_presses();
+}
+
+fn retry_system(
+    keyboard: Res<Input<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_bindings: Res<GamepadBindings>,
+    active_gamepad: Res<ActiveGamepad>,
+    mut restart_events: EventWriter<RestartEvent>,
+) {
+    let gamepad_retry = active_gamepad.0.is_some_and(|gamepad| {
+        gamepad_bindings.just_pressed(&gamepad_buttons, gamepad, Action::Retry)
+    });
+
+    if key_bindings.just_pressed(&keyboard, Action::Retry) || gamepad_retry {
+        restart_events.send(RestartEvent::All);
+    }
 }
@@ -0,0 +1,317 @@
+use bevy::prelude::*;
+
+use crate::GameStates;
+
+use super::key_bindings::{Action, GamepadBindings, KeyBindings, BINDABLE_KEYS};
+use super::ui_plugin::{button, button_system, button_text};
+use super::{despawn_with, FontHandles};
+
+//region Plugin Boilerplate
+pub struct ControlsMenuPlugin;
+
+impl Plugin for ControlsMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(RebindCapture::default())
+            .add_system_set(
+                SystemSet::on_enter(GameStates::Controls).with_system(controls_menu_setup_system),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameStates::Controls)
+                    .with_system(button_system)
+                    .with_system(controls_menu_button_system)
+                    .with_system(capture_key_system)
+                    .with_system(capture_gamepad_button_system)
+                    .with_system(controls_menu_text_system),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameStates::Controls)
+                    .with_system(controls_menu_exit_system)
+                    .with_system(despawn_with::<ControlsMenuEntity>),
+            );
+    }
+}
+//endregion
+
+//region Controls Menu Resources and Components
+/// The action waiting for a key or gamepad button to be pressed, and what the menu tells the player
+#[derive(Default)]
+struct RebindCapture {
+    action: Option<Action>,
+    status: String,
+}
+
+/// Everything spawned by the controls menu, despawned when leaving it
+#[derive(Component)]
+struct ControlsMenuEntity;
+
+#[derive(Component)]
+enum ControlsMenuButton {
+    /// Wait for a key or gamepad button to add to the action
+    Add(Action),
+    Clear(Action),
+    ResetAll,
+    Back,
+}
+
+#[derive(Component)]
+struct ActionKeysText(Action);
+
+#[derive(Component)]
+struct StatusText;
+//endregion
+
+fn controls_menu_setup_system(
+    mut commands: Commands,
+    fonts: Res<FontHandles>,
+    mut windows: ResMut<Windows>,
+    mut capture: ResMut<RebindCapture>,
+) {
+    windows.get_primary_mut().unwrap().set_cursor_visibility(true);
+    *capture = RebindCapture::default();
+
+    let font = fonts.rubik_regular.clone();
+    let small_button = |width: f32| ButtonBundle {
+        style: Style {
+            size: Size::new(Val::Px(width), Val::Px(30.)),
+            margin: UiRect::all(Val::Px(2.)),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..Default::default()
+        },
+        ..button(Color::DARK_GRAY)
+    };
+    let transparent = UiColor(Color::rgba(0., 0., 0., 0.0));
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+            },
+            color: transparent,
+            ..Default::default()
+        })
+        .insert(ControlsMenuEntity)
+        .with_children(|parent| {
+            parent.spawn_bundle(
+                TextBundle::from_section(
+                    "Controls",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 48.,
+                        color: Color::WHITE,
+                    },
+                )
+                .with_style(Style {
+                    margin: UiRect::all(Val::Px(10.)),
+                    ..Default::default()
+                }),
+            );
+
+            // One row per action: its name, its keys, and the buttons to change them
+            for action in Action::ALL {
+                parent
+                    .spawn_bundle(NodeBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(850.), Val::Px(34.)),
+                            align_items: AlignItems::Center,
+                            ..Default::default()
+                        },
+                        color: transparent,
+                        ..Default::default()
+                    })
+                    .with_children(|parent| {
+                        parent.spawn_bundle(
+                            button_text(Color::WHITE, &font, action.name()).with_style(Style {
+                                size: Size::new(Val::Px(150.), Val::Auto),
+                                ..Default::default()
+                            }),
+                        );
+                        parent
+                            .spawn_bundle(button_text(Color::GRAY, &font, "").with_style(Style {
+                                size: Size::new(Val::Px(480.), Val::Auto),
+                                ..Default::default()
+                            }))
+                            .insert(ActionKeysText(action));
+
+                        for (label, menu_button) in [
+                            ("Add", ControlsMenuButton::Add(action)),
+                            ("Clear", ControlsMenuButton::Clear(action)),
+                        ] {
+                            parent
+                                .spawn_bundle(small_button(100.))
+                                .with_children(|parent| {
+                                    parent.spawn_bundle(button_text(Color::WHITE, &font, label));
+                                })
+                                .insert(menu_button);
+                        }
+                    });
+            }
+
+            parent
+                .spawn_bundle(button_text(Color::GOLD, &font, ""))
+                .insert(StatusText);
+
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Auto, Val::Auto),
+                        margin: UiRect::all(Val::Px(10.)),
+                        ..Default::default()
+                    },
+                    color: transparent,
+                    ..Default::default()
+                })
+                .with_children(|parent| {
+                    for (label, menu_button) in [
+                        ("Reset to defaults", ControlsMenuButton::ResetAll),
+                        ("Back (Esc)", ControlsMenuButton::Back),
+                    ] {
+                        parent
+                            .spawn_bundle(small_button(200.))
+                            .with_children(|parent| {
+                                parent.spawn_bundle(button_text(Color::WHITE, &font, label));
+                            })
+                            .insert(menu_button);
+                    }
+                });
+        });
+}
+
+fn controls_menu_exit_system(mut windows: ResMut<Windows>) {
+    windows.get_primary_mut().unwrap().set_cursor_visibility(false);
+}
+
+fn controls_menu_button_system(
+    buttons: Query<(&Interaction, &ControlsMenuButton), Changed<Interaction>>,
+    mut key_bindings: ResMut<KeyBindings>,
+    mut gamepad_bindings: ResMut<GamepadBindings>,
+    mut capture: ResMut<RebindCapture>,
+    mut game_state: ResMut<State<GameStates>>,
+) {
+    for (interaction, menu_button) in buttons.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+
+        match menu_button {
+            ControlsMenuButton::Add(action) => {
+                capture.action = Some(*action);
+                capture.status = format!(
+                    "Press a key or gamepad button for {} (Escape cancels)",
+                    action.name()
+                );
+            }
+            ControlsMenuButton::Clear(action) => {
+                key_bindings.set_keys(*action, vec![]);
+                gamepad_bindings.set_buttons(*action, vec![]);
+                capture.status = format!("{} has no keys or buttons", action.name());
+            }
+            ControlsMenuButton::ResetAll => {
+                *key_bindings = KeyBindings::default();
+                *gamepad_bindings = GamepadBindings::default();
+                capture.status = "Every action has its default keys and buttons".to_string();
+            }
+            ControlsMenuButton::Back => leave_controls_menu(&mut game_state),
+        }
+    }
+}
+
+/// Back to the menu the controls menu was pushed on top of
+fn leave_controls_menu(game_state: &mut State<GameStates>) {
+    game_state.overwrite_pop().unwrap();
+}
+
+fn capture_key_system(
+    keyboard: Res<Input<KeyCode>>,
+    mut key_bindings: ResMut<KeyBindings>,
+    mut capture: ResMut<RebindCapture>,
+    mut game_state: ResMut<State<GameStates>>,
+) {
+    let key = match keyboard.get_just_pressed().next() {
+        Some(key) => *key,
+        None => return,
+    };
+
+    let action = match capture.action {
+        Some(action) => action,
+        None => {
+            if key == KeyCode::Escape {
+                leave_controls_menu(&mut game_state);
+            }
+            return;
+        }
+    };
+
+    capture.action = None;
+    if key == KeyCode::Escape {
+        capture.status = String::new();
+        return;
+    }
+    // Hardwired keys would fire two things at once, and anything else couldn't be written to the config file
+    if !BINDABLE_KEYS.contains(&key) {
+        capture.status = format!("{key:?} can't be bound");
+        return;
+    }
+
+    let conflicts = key_bindings.bind(action, key);
+    capture.status = if conflicts.is_empty() {
+        format!("{key:?} was bound to {}", action.name())
+    } else {
+        let names: Vec<&str> = conflicts.iter().map(Action::name).collect();
+        format!("{key:?} was bound to {}, and unbound from {}", action.name(), names.join(", "))
+    };
+}
+
+fn capture_gamepad_button_system(
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    mut gamepad_bindings: ResMut<GamepadBindings>,
+    mut capture: ResMut<RebindCapture>,
+) {
+    let action = match capture.action {
+        Some(action) => action,
+        None => return,
+    };
+    // Any connected gamepad can be used to rebind
+    let button = match gamepad_buttons.get_just_pressed().next() {
+        Some(button) => button.button_type,
+        None => return,
+    };
+
+    capture.action = None;
+    let conflicts = gamepad_bindings.bind(action, button);
+    capture.status = if conflicts.is_empty() {
+        format!("{button:?} was bound to {}", action.name())
+    } else {
+        let names: Vec<&str> = conflicts.iter().map(Action::name).collect();
+        format!("{button:?} was bound to {}, and unbound from {}", action.name(), names.join(", "))
+    };
+}
+
+fn controls_menu_text_system(
+    key_bindings: Res<KeyBindings>,
+    gamepad_bindings: Res<GamepadBindings>,
+    capture: Res<RebindCapture>,
+    mut keys_text: Query<(&mut Text, &ActionKeysText), Without<StatusText>>,
+    mut status_text: Query<&mut Text, With<StatusText>>,
+) {
+    for (mut text, keys) in keys_text.iter_mut() {
+        text.sections[0].value = if capture.action == Some(keys.0) {
+            "...".to_string()
+        } else {
+            let or_none = |names: String| if names.is_empty() { "(none)".to_string() } else { names };
+            format!(
+                "{} | {}",
+                or_none(key_bindings.key_names(keys.0)),
+                or_none(gamepad_bindings.button_names(keys.0))
+            )
+        };
+    }
+
+    for mut text in status_text.iter_mut() {
+        text.sections[0].value = capture.status.clone();
+    }
+}
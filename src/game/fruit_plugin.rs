@@ -1,18 +1,21 @@
-use crate::game::common_components::{GravityAffects, IsOnWall, TimeAnimation, Velocity, Walls};
-use crate::game::common_systems::RestartEvent;
+use crate::game::common_components::{
+    GameEntity, GravityAffects, IsOnWall, TimeAnimation, Velocity, Walls,
+};
 use crate::game::{
-    Score, TexturesHandles, FRUITS_SCALE, FRUITS_SIZE, FRUIT_SPEED, MAX_FRUIT_PIECE_SPEED,
+    TexturesHandles, FRUITS_SCALE, FRUITS_SIZE, FRUIT_CUTTABLE_SPEED, MAX_FRUIT_PIECE_SPEED,
     NUMBER_OF_FRUIT_PIECES,
 };
 use bevy::prelude::*;
-use rand::{thread_rng, Rng};
-use crate::GameStates;
+use rand::Rng;
 
+use super::common_systems::{PhysicsClock, PhysicsStage};
 use super::beatmap_plugin::{Beatmap, BeatmapPlayback};
-use super::osu_reader::OsuFileSection;
+use super::mods::Mods;
+use super::scoring::{HitWindows, Judgement, JudgementEvent, ScoreState};
 use super::{
-    BEATMAP_MUSIC_OFFSET_TIME, EFFECTIVE_SCREEN_WIDTH_PERCENT, FRUITS_GRAVITY_FALL,
-    FRUITS_GRAVITY_HOLD, FRUITS_GRAVITY_UP, GameSettings,
+    trajectory, GameRng, BEATMAP_MUSIC_OFFSET_TIME, EFFECTIVE_SCREEN_HEIGHT_PERCENT,
+    EFFECTIVE_SCREEN_WIDTH_PERCENT, FRUITS_GRAVITY_FALL, FRUITS_GRAVITY_HOLD, FRUITS_GRAVITY_UP,
+    OSU_PLAYFIELD_HEIGHT,
 };
 
 //region Plugin Boilerplate
@@ -21,22 +24,16 @@ pub struct FruitPlugin;
 impl Plugin for FruitPlugin {
     fn build(&self, app: &mut App) {
         app
-            .add_system_set(
-            SystemSet::on_update(GameStates::Game)
-                .label("corners_and_reach_bottom")
-                .with_system(fruit_corners_system)
-                .with_system(fruits_reach_bottom_system)
-        )
-
-            .add_system_set(
-            SystemSet::on_update(GameStates::Game)
-                .with_system(spawn_fruit_system).after("corners_and_reach_bottom")
-        )
-            .add_system_set(
-                SystemSet::on_update(GameStates::Game)
-                    .with_system(fruits_get_cut_system)
-                    .with_system(fruits_cuttable_system)
-                    .with_system(fruit_part_eliminate_system)
+            .add_event::<JudgementEvent>()
+            .add_system_set_to_stage(
+                PhysicsStage, // Once per fixed step, see CommonSystems for the order
+                SystemSet::new()
+                    .with_system(fruits_cuttable_system.label("gravity_strength").after("step_start"))
+                    .with_system(fruit_corners_system.label("corners").after("move"))
+                    .with_system(fruits_get_cut_system.label("cuts").after("collisions"))
+                    .with_system(fruits_reach_bottom_system.label("reach_bottom").after("cuts"))
+                    .with_system(fruit_part_eliminate_system.after("corners"))
+                    .with_system(spawn_fruit_system.after("reach_bottom"))
             );
     }
 }
@@ -46,6 +43,7 @@ impl Plugin for FruitPlugin {
 #[derive(Component)]
 pub struct Fruit {
     pub texture_id: usize, // The id of the fruit's texture, chosen randomly
+    pub hit_time: u32,     // The time of its HitObject, which cuts are judged against
 }
 
 #[derive(Component)]
@@ -64,82 +62,103 @@ fn spawn_fruit_system(
     window: Res<Windows>,
     textures: Res<TexturesHandles>,
     beatmap: Res<Beatmap>,
-    time: Res<Time>,
+    physics_clock: Res<PhysicsClock>,
+    mods: Res<Mods>,
+    mut game_rng: ResMut<GameRng>,
 ) {
-    // Don't spawn any fruits until the beatmap has started
-    if !beatmap_playback.beatmap_started {
-        return;
-    }
+    let window = window.get_primary().unwrap();
+    let effective_width = window.width() * EFFECTIVE_SCREEN_WIDTH_PERCENT;
+    let effective_height = window.height() * EFFECTIVE_SCREEN_HEIGHT_PERCENT;
+    let y_spawn_position = -window.height() / 2. - 50.;
+    let gravity_up = FRUITS_GRAVITY_UP * mods.gravity_scale();
+    // Fruits rise in real time, while the song may be sped up or slowed down
+    let rise_millis = |launch: trajectory::FruitLaunch| {
+        launch.rise_time as f64 * 1000. * mods.playback_rate()
+    };
+
+    // Fruits cut higher up rise for longer, so they may have to spawn before earlier ones.
+    // No fruit rises for longer than one cut at the top of the screen
+    let max_rise_millis = rise_millis(trajectory::solve_launch(
+        y_spawn_position,
+        effective_height / 2.,
+        gravity_up,
+    ));
+
+    let mut hit_object_id = beatmap_playback.current_hit_object_id;
+    while let Some(hit_object) = beatmap.0.hit_objects.get(hit_object_id) {
+        // Stop at the first fruit that can't be due yet
+        if physics_clock.position < hit_object.time as f64 - max_rise_millis {
+            break;
+        }
 
-    // Get the amount of milliseconds since the beatmap started playing
-    let current_millis = (beatmap_playback
-        .play_timer
-        .tick(time.delta())
-        .elapsed_secs()
-        * 1000.) as u32;
-
-    // Get the vector of HitObjects from the Beatmap data
-    if let Some(section) = beatmap.0.get("[HitObjects]") {
-        if let OsuFileSection::HitObjects(hit_objects) = section {
-            // Get the current HitObject from the vector of HitObjects
-            let hit_object = &hit_objects[beatmap_playback.current_hit_object_id];
-
-            // Return if it's still not time to spawn the fruit
-            if current_millis < hit_object.time {
-                return;
-            }
-
-            beatmap_playback.current_hit_object_id += 1;
-
-            // Random fruit generation
-            let number_of_fruits = textures.fruits.len();
-            let index_of_fruit = thread_rng().gen_range(0..number_of_fruits);
-            let texture = textures.fruits[index_of_fruit].clone();
-
-            // Random position generation
-            let window = window.get_primary().unwrap();
-            let effective_width = window.width() * EFFECTIVE_SCREEN_WIDTH_PERCENT;
-            let y_spawn_position = -window.height() / 2. - 50.;
-            let x_spawn_position =
-                (hit_object.position.x / 640. * effective_width) - effective_width / 2.;
-
-            // Calculations for the fruit speed (gone sorta wrong)
-            let y_speed_offset = (0.5 - hit_object.position.y / 480.) * 10.;
-
-            // ut = s - 1/2at^2
-            // u = (s - 1/2at^2) / t
-            // u = s/t - 1/2at
+        let id = hit_object_id;
+        hit_object_id += 1;
 
-            commands
-                .spawn_bundle(SpriteBundle {
-                    texture,
-                    transform: Transform {
-                        translation: Vec3::new(x_spawn_position, y_spawn_position, 0.0),
-                        scale: FRUITS_SCALE,
-                        ..Default::default()
-                    },
+        if beatmap_playback.spawned_ahead.contains(&id) {
+            continue;
+        }
+
+        // The height at which the fruit has to become cuttable (osu! y goes downwards)
+        let y_cut_position = (0.5 - hit_object.position.y / OSU_PLAYFIELD_HEIGHT) * effective_height;
+        let launch = trajectory::solve_launch(y_spawn_position, y_cut_position, gravity_up);
+
+        // Skip it if it's still not time to spawn the fruit
+        if physics_clock.position < hit_object.time as f64 - rise_millis(launch) {
+            continue;
+        }
+
+        beatmap_playback.spawned_ahead.insert(id);
+
+        // Random fruit generation
+        let number_of_fruits = textures.fruits.len();
+        let index_of_fruit = game_rng.rng.gen_range(0..number_of_fruits);
+        let texture = textures.fruits[index_of_fruit].clone();
+
+        // Position generation
+        let x_spawn_position =
+            (hit_object.position.x / 640. * effective_width) - effective_width / 2.;
+
+        commands
+            .spawn_bundle(SpriteBundle {
+                texture,
+                transform: Transform {
+                    translation: Vec3::new(x_spawn_position, y_spawn_position, 0.0),
+                    scale: FRUITS_SCALE * mods.fruit_scale(),
                     ..Default::default()
-                })
-                .insert(Velocity {
-                    x: thread_rng().gen_range(-0.4..0.4),
-                    y: FRUIT_SPEED + y_speed_offset,
-                })
-                .insert(GravityAffects {
-                    strength: FRUITS_GRAVITY_UP,
-                })
-                .insert(IsOnWall(None))
-                .insert(Fruit {
-                    texture_id: index_of_fruit,
-                })
-                .insert(CutAffects {
-                    is_cut: false,
-                    can_be_cut: false,
-                })
-                .insert(TimeAnimation::from_callback(|tf, _, t| {
+                },
+                ..Default::default()
+            })
+            .insert(GameEntity)
+            .insert(Velocity {
+                x: game_rng.rng.gen_range(-0.4..0.4),
+                y: launch.velocity,
+            })
+            .insert(GravityAffects {
+                strength: gravity_up,
+            })
+            .insert(IsOnWall(None))
+            .insert(Fruit {
+                texture_id: index_of_fruit,
+                hit_time: hit_object.time,
+            })
+            .insert(CutAffects {
+                is_cut: false,
+                can_be_cut: false,
+            })
+            .insert(TimeAnimation {
+                callback: |tf, data, t| {
                     tf.rotation = Quat::from_rotation_z(t * 4.0);
-                    tf.scale = FRUITS_SCALE * (1. / BEATMAP_MUSIC_OFFSET_TIME * t).min(1.);
-                }));
-        }
+                    tf.scale = FRUITS_SCALE * data[0] * (1. / BEATMAP_MUSIC_OFFSET_TIME * t).min(1.);
+                },
+                data: vec![mods.fruit_scale()],
+                time: 0.,
+            });
+    }
+
+    // Everything before current_hit_object_id has been spawned
+    let playback = &mut *beatmap_playback;
+    while playback.spawned_ahead.remove(&playback.current_hit_object_id) {
+        playback.current_hit_object_id += 1;
     }
 }
 
@@ -178,19 +197,18 @@ fn fruit_part_eliminate_system(
 }
 
 fn fruits_reach_bottom_system(
-    mut query: Query<(Entity, &IsOnWall), With<Fruit>>,
-    mut restart_events: EventWriter<RestartEvent>,
-    game_settings: Res<GameSettings>,
+    mut commands: Commands,
+    mut query: Query<(Entity, &IsOnWall, &CutAffects), With<Fruit>>,
+    mut score: ResMut<ScoreState>,
+    mut judgement_events: EventWriter<JudgementEvent>,
 ) {
-    for (_, wall) in query.iter_mut() {
-        // If the fruit hits the floor
-        if let Some(_) = wall.0 {
-            // Request game to be restarted
-            if !game_settings.no_death_penalty {
-                restart_events.send_default();
-            }
-
-            break;
+    for (entity, wall, cut_affects) in query.iter_mut() {
+        // If the fruit hits the floor (a fruit cut this step was already judged)
+        if wall.0.is_some() && !cut_affects.is_cut {
+            // It was never cut. The HP bar decides whether the run fails
+            score.judge(Judgement::Miss);
+            judgement_events.send(JudgementEvent(Judgement::Miss));
+            commands.entity(entity).despawn();
         }
     }
 }
@@ -198,15 +216,28 @@ fn fruits_reach_bottom_system(
 fn fruits_get_cut_system(
     mut commands: Commands,
     query: Query<(Entity, &Transform, &CutAffects, &Fruit)>,
-    mut score: ResMut<Score>,
+    mut score: ResMut<ScoreState>,
+    mut judgement_events: EventWriter<JudgementEvent>,
     textures: Res<TexturesHandles>,
+    beatmap: Res<Beatmap>,
+    physics_clock: Res<PhysicsClock>,
+    mods: Res<Mods>,
+    mut game_rng: ResMut<GameRng>,
 ) {
+    let hit_windows = HitWindows::from_overall_difficulty(
+        mods.overall_difficulty(beatmap.0.difficulty.overall_difficulty),
+    );
+
     for (entity, transform, cut_affects, fruit) in query.iter() {
         if !cut_affects.is_cut {
             continue;
         }
 
-        score.0 += 1;
+        // Judge the cut by how far from the beat it was
+        let error = physics_clock.position - fruit.hit_time as f64;
+        let judgement = hit_windows.judge(error);
+        score.judge_hit(judgement, error);
+        judgement_events.send(JudgementEvent(judgement));
 
         // Spawn as many Fruit Parts as in NUMBER_OF_FRUIT_PIECES
         for part_id in 0..(NUMBER_OF_FRUIT_PIECES as usize) {
@@ -214,8 +245,8 @@ fn fruits_get_cut_system(
 
             let translation = transform.translation;
 
-            let x_vl = thread_rng().gen_range(-MAX_FRUIT_PIECE_SPEED..MAX_FRUIT_PIECE_SPEED);
-            let y_vl = thread_rng().gen_range(0.0..MAX_FRUIT_PIECE_SPEED);
+            let x_vl = game_rng.rng.gen_range(-MAX_FRUIT_PIECE_SPEED..MAX_FRUIT_PIECE_SPEED);
+            let y_vl = game_rng.rng.gen_range(0.0..MAX_FRUIT_PIECE_SPEED);
 
             commands
                 .spawn_bundle(SpriteSheetBundle {
@@ -232,6 +263,7 @@ fn fruits_get_cut_system(
                     ..Default::default()
                 })
                 .insert(FruitPart) // It's a part of a fruit
+                .insert(GameEntity)
                 .insert(Velocity { x: x_vl, y: y_vl }) // The pieces of fruit explode
                 .insert(GravityAffects {
                     strength: FRUITS_GRAVITY_FALL * 2.,
@@ -243,8 +275,8 @@ fn fruits_get_cut_system(
                         tf.scale = FRUITS_SCALE * 0.75 * (1. - 0.5 * t);
                     },
                     data: vec![
-                        thread_rng().gen_range(2.0..4.0)
-                            * if thread_rng().gen_bool(0.5) { 1. } else { -1. },
+                        game_rng.rng.gen_range(2.0..4.0)
+                            * if game_rng.rng.gen_bool(0.5) { 1. } else { -1. },
                     ],
                     time: 0.,
                 }); // We check whether it hit the floor to despawn
@@ -255,17 +287,29 @@ fn fruits_get_cut_system(
 }
 
 fn fruits_cuttable_system(
-    mut query: Query<(&Velocity, &mut Sprite, &mut CutAffects, &mut GravityAffects), With<Fruit>>,
+    mut query: Query<(&Velocity, &Fruit, &mut Sprite, &mut CutAffects, &mut GravityAffects)>,
+    beatmap: Res<Beatmap>,
+    physics_clock: Res<PhysicsClock>,
+    mods: Res<Mods>,
 ) {
-    for (velocity, mut sprite, mut cut_affects, mut gravity_affects) in query.iter_mut() {
-        cut_affects.can_be_cut = velocity.y <= 2.;
-        if velocity.y < 0. {
-            gravity_affects.strength = FRUITS_GRAVITY_FALL;
-        } else if cut_affects.can_be_cut {
-            gravity_affects.strength = FRUITS_GRAVITY_HOLD;
-        } else {
-            gravity_affects.strength = FRUITS_GRAVITY_UP;
-        }
+    let hit_windows = HitWindows::from_overall_difficulty(
+        mods.overall_difficulty(beatmap.0.difficulty.overall_difficulty),
+    );
+
+    for (velocity, fruit, mut sprite, mut cut_affects, mut gravity_affects) in query.iter_mut() {
+        // The launch is solved so the fruit slows down on its hit time, but it can be cut
+        // as early as the judgement windows allow
+        let slowed_down = velocity.y <= FRUIT_CUTTABLE_SPEED;
+        cut_affects.can_be_cut =
+            slowed_down || physics_clock.position >= fruit.hit_time as f64 - hit_windows.good;
+        gravity_affects.strength = mods.gravity_scale()
+            * if velocity.y < 0. {
+                FRUITS_GRAVITY_FALL
+            } else if slowed_down {
+                FRUITS_GRAVITY_HOLD
+            } else {
+                FRUITS_GRAVITY_UP
+            };
 
         sprite.color = if cut_affects.can_be_cut {
             Color::WHITE
@@ -0,0 +1,126 @@
+use bevy::prelude::*;
+
+use crate::GameStates;
+
+use super::beatmap_plugin::Beatmap;
+use super::common_systems::{PhysicsClock, PhysicsStage, RestartEvent};
+use super::mods::{Mod, Mods};
+use super::scoring::{Judgement, JudgementEvent};
+use super::{
+    HP_DRAIN_PER_SECOND, HP_FALL_PENALTY, HP_MISS_PENALTY, HP_PENALTY_PER_DRAIN_RATE,
+    HP_RECOVERY_GOOD, HP_RECOVERY_GREAT, HP_RECOVERY_PERFECT,
+};
+
+//region Plugin Boilerplate
+pub struct HealthPlugin;
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set_to_stage(
+            PhysicsStage, // Whether a run fails has to play out the same way as the fruits
+            SystemSet::new()
+                .with_system(health_drain_system.label("health").after("reach_bottom"))
+                .with_system(health_judgement_system.label("health").after("reach_bottom"))
+                .with_system(health_fail_system.after("health")),
+        )
+        .add_system_set(
+            SystemSet::on_update(GameStates::Game).with_system(health_restart_system),
+        );
+    }
+}
+//endregion
+
+/// The HP bar, from 0.0 (failed) to 1.0 (full)
+pub struct Health(pub f32);
+
+impl Default for Health {
+    fn default() -> Self {
+        Health(1.)
+    }
+}
+
+impl Health {
+    fn change(&mut self, amount: f32) {
+        self.0 = (self.0 + amount).clamp(0., 1.);
+    }
+}
+
+/// How much HP is lost on misses and falls. Higher HPDrainRates punish harder, like in osu!
+fn penalty(base: f32, beatmap: &Beatmap) -> f32 {
+    base + HP_PENALTY_PER_DRAIN_RATE * beatmap.0.difficulty.hp_drain_rate
+}
+
+fn health_drain_system(
+    mut health: ResMut<Health>,
+    beatmap: Res<Beatmap>,
+    physics_clock: Res<PhysicsClock>,
+    mods: Res<Mods>,
+) {
+    let hit_objects = &beatmap.0.hit_objects;
+    let (first, last) = match (hit_objects.first(), hit_objects.last()) {
+        (Some(first), Some(last)) => (first.time, beatmap.0.end_time(last)),
+        _ => return,
+    };
+
+    // HP only drains while there are fruits to cut
+    let position = physics_clock.position;
+    if position < first as f64 || position > last as f64 {
+        return;
+    }
+
+    let in_break = beatmap.0.events.breaks.iter().any(|break_period| {
+        (break_period.start_time as f64..break_period.end_time as f64).contains(&position)
+    });
+    if in_break {
+        return;
+    }
+
+    // Drains per second of the song, which Double Time and Half Time speed up or slow down
+    let song_seconds = (PhysicsClock::step_millis(mods.playback_rate()) / 1000.) as f32;
+    health.change(-HP_DRAIN_PER_SECOND * beatmap.0.difficulty.hp_drain_rate * song_seconds);
+}
+
+fn health_judgement_system(
+    mut health: ResMut<Health>,
+    mut judgement_events: EventReader<JudgementEvent>,
+    beatmap: Res<Beatmap>,
+) {
+    for event in judgement_events.iter() {
+        health.change(match event.0 {
+            Judgement::Perfect => HP_RECOVERY_PERFECT,
+            Judgement::Great => HP_RECOVERY_GREAT,
+            Judgement::Good => HP_RECOVERY_GOOD,
+            Judgement::Miss => -penalty(HP_MISS_PENALTY, &beatmap),
+        });
+    }
+}
+
+fn health_restart_system(
+    mut health: ResMut<Health>,
+    mut restart_events: EventReader<RestartEvent>,
+    beatmap: Res<Beatmap>,
+) {
+    for event in restart_events.iter() {
+        match event {
+            // Retrying starts with a full bar
+            RestartEvent::All => *health = Health::default(),
+            // The player fell off the stage
+            RestartEvent::OnlyPlayer => health.change(-penalty(HP_FALL_PENALTY, &beatmap)),
+        }
+    }
+}
+
+fn health_fail_system(
+    health: Res<Health>,
+    mut restart_events: EventWriter<RestartEvent>,
+    mods: Res<Mods>,
+    mut failed: Local<bool>,
+) {
+    // The run only fails once the bar is empty. It restarts next frame, so the steps left in
+    // this one must not fail it again
+    let empty = health.0 <= 0. && !mods.is_enabled(Mod::NoFail);
+    if empty && !*failed {
+        restart_events.send_default();
+    }
+    *failed = empty;
+}
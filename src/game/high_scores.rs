@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+
+use crate::GameStates;
+
+use super::beatmap_library::{LibraryBeatmap, SelectedBeatmap};
+use super::mods::{Mod, Mods};
+use super::scoring::{JudgementCounts, ScoreState};
+use super::{user_dirs, GameSettings, HIGH_SCORES_KEPT};
+
+/// First line of the scores file, followed by the version of the format
+const SCORES_FILE_HEADER: &str = "need_4_fruits scores v";
+/// The version written by this build. Files from older versions are read and upgraded on the next save.
+/// v2 added the mods
+const SCORES_FILE_VERSION: u32 = 2;
+const SCORES_FILE_NAME: &str = "scores.txt";
+
+//region Plugin Boilerplate
+pub struct HighScoresPlugin;
+
+impl Plugin for HighScoresPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(load_high_scores_system)
+            .insert_resource(RecordedScore::default())
+            .add_system_set(
+                SystemSet::on_enter(GameStates::Results)
+                    .with_system(record_high_score_system.label("record_high_score")),
+            );
+    }
+}
+//endregion
+
+//region High Score Resources
+/// Identifies a difficulty. The hash alone is unique, the difficulty name is kept so the file can be read
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BeatmapKey {
+    pub hash: String,
+    pub version: String,
+}
+
+impl From<&LibraryBeatmap> for BeatmapKey {
+    fn from(beatmap: &LibraryBeatmap) -> Self {
+        BeatmapKey {
+            hash: beatmap.hash.clone(),
+            version: beatmap.version.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HighScore {
+    pub score: u64,
+    pub accuracy: f32,
+    pub max_combo: usize,
+    pub judgements: JudgementCounts,
+    /// The GameSettings toggles that were on, see GameSettings::enabled
+    pub settings: Vec<String>,
+    pub mods: Mods,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+impl HighScore {
+    pub fn new(score: &ScoreState, settings: &GameSettings, mods: &Mods) -> Self {
+        HighScore {
+            score: score.total_score,
+            accuracy: score.accuracy(),
+            max_combo: score.max_combo,
+            judgements: score.judgements,
+            settings: settings.enabled().iter().map(|name| name.to_string()).collect(),
+            mods: mods.clone(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs()),
+        }
+    }
+
+    /// e.g. "1234567  98.50%  312x  +HRDT  (snap_on_cut)  3 days ago"
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{}  {:.2}%  {}x", self.score, self.accuracy, self.max_combo);
+        if !self.mods.is_empty() {
+            summary += &format!("  +{}", self.mods);
+        }
+        if !self.settings.is_empty() {
+            summary += &format!("  ({})", self.settings.join(", "));
+        }
+        summary + "  " + &format_age(self.timestamp)
+    }
+}
+
+/// Every local score, best first for each difficulty
+pub struct HighScores {
+    scores: HashMap<BeatmapKey, Vec<HighScore>>,
+    path: PathBuf,
+    /// False when the file was written by a newer version, which this one must not overwrite
+    writable: bool,
+}
+
+/// Where the score of the last run ended up in its difficulty's list, for the results screen
+#[derive(Default)]
+pub struct RecordedScore {
+    /// None if the score wasn't good enough to be kept
+    pub rank: Option<usize>,
+}
+
+#[derive(Debug)]
+pub enum HighScoresError {
+    Io(io::Error),
+    /// The file doesn't start with SCORES_FILE_HEADER
+    UnknownFormat,
+    /// The file was written by a newer version of the game
+    NewerVersion(u32),
+}
+
+impl fmt::Display for HighScoresError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HighScoresError::Io(error) => write!(f, "{error}"),
+            HighScoresError::UnknownFormat => write!(f, "not a scores file"),
+            HighScoresError::NewerVersion(version) => write!(
+                f,
+                "written by a newer version of the game (v{version}, this one reads up to v{SCORES_FILE_VERSION})"
+            ),
+        }
+    }
+}
+
+impl From<io::Error> for HighScoresError {
+    fn from(error: io::Error) -> Self {
+        HighScoresError::Io(error)
+    }
+}
+//endregion
+
+impl HighScores {
+    pub fn empty(path: &Path) -> Self {
+        HighScores {
+            scores: HashMap::new(),
+            path: path.to_path_buf(),
+            writable: true,
+        }
+    }
+
+    /// Read the scores file. A missing file is an empty score list
+    pub fn load(path: &Path) -> Result<Self, HighScoresError> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                return Ok(HighScores::empty(path))
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        let mut lines = contents.lines();
+        let version: u32 = lines
+            .next()
+            .and_then(|header| header.strip_prefix(SCORES_FILE_HEADER))
+            .and_then(|version| version.trim().parse().ok())
+            .ok_or(HighScoresError::UnknownFormat)?;
+        if version > SCORES_FILE_VERSION {
+            return Err(HighScoresError::NewerVersion(version));
+        }
+
+        let mut high_scores = HighScores::empty(path);
+        for line in lines.filter(|line| !line.trim().is_empty()) {
+            match parse_score_line(line, version) {
+                Some((key, score)) => high_scores.scores.entry(key).or_default().push(score),
+                None => warn!("Skipping invalid score: {line}"),
+            }
+        }
+        for scores in high_scores.scores.values_mut() {
+            sort_scores(scores);
+        }
+
+        Ok(high_scores)
+    }
+
+    /// Write every score back to the file, replacing it in one go so a crash can't corrupt it
+    pub fn save(&self) -> Result<(), HighScoresError> {
+        if !self.writable {
+            return Ok(());
+        }
+
+        let mut contents = format!("{SCORES_FILE_HEADER}{SCORES_FILE_VERSION}\n");
+        for (key, scores) in &self.scores {
+            for score in scores {
+                contents += &score_line(key, score);
+                contents.push('\n');
+            }
+        }
+
+        user_dirs::write_atomically(&self.path, &contents)?;
+        Ok(())
+    }
+
+    /// Add a score, returning its rank (0 is the best) if it is among the HIGH_SCORES_KEPT best
+    pub fn add(&mut self, key: BeatmapKey, score: HighScore) -> Option<usize> {
+        let scores = self.scores.entry(key).or_default();
+        // Ties go to the older score
+        let rank = scores
+            .iter()
+            .position(|other| score.score > other.score)
+            .unwrap_or(scores.len());
+
+        scores.insert(rank, score);
+        scores.truncate(HIGH_SCORES_KEPT);
+
+        (rank < HIGH_SCORES_KEPT).then_some(rank)
+    }
+
+    /// The best `count` scores of a difficulty, best first
+    pub fn top(&self, key: &BeatmapKey, count: usize) -> &[HighScore] {
+        match self.scores.get(key) {
+            Some(scores) => &scores[..count.min(scores.len())],
+            None => &[],
+        }
+    }
+
+    pub fn personal_best(&self, key: &BeatmapKey) -> Option<&HighScore> {
+        self.top(key, 1).first()
+    }
+}
+
+fn sort_scores(scores: &mut Vec<HighScore>) {
+    scores.sort_by(|a, b| b.score.cmp(&a.score).then(a.timestamp.cmp(&b.timestamp)));
+    scores.truncate(HIGH_SCORES_KEPT);
+}
+
+//region Scores File Format
+// One score per line, fields separated by tabs:
+// hash, difficulty name, score, accuracy, max combo, perfects, greats, goods, misses,
+// comma separated settings, timestamp, mod acronyms (since v2)
+
+fn score_line(key: &BeatmapKey, score: &HighScore) -> String {
+    let judgements = &score.judgements;
+
+    [
+        key.hash.clone(),
+        key.version.replace('\t', " "),
+        score.score.to_string(),
+        score.accuracy.to_string(),
+        score.max_combo.to_string(),
+        judgements.perfect.to_string(),
+        judgements.great.to_string(),
+        judgements.good.to_string(),
+        judgements.miss.to_string(),
+        score.settings.join(","),
+        score.timestamp.to_string(),
+        score.mods.to_string(),
+    ]
+    .join("\t")
+}
+
+fn parse_score_line(line: &str, version: u32) -> Option<(BeatmapKey, HighScore)> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() < 11 {
+        return None;
+    }
+
+    let key = BeatmapKey {
+        hash: fields[0].to_string(),
+        version: fields[1].to_string(),
+    };
+    let mut score = HighScore {
+        score: fields[2].parse().ok()?,
+        accuracy: fields[3].parse().ok()?,
+        max_combo: fields[4].parse().ok()?,
+        judgements: JudgementCounts {
+            perfect: fields[5].parse().ok()?,
+            great: fields[6].parse().ok()?,
+            good: fields[7].parse().ok()?,
+            miss: fields[8].parse().ok()?,
+        },
+        settings: fields[9]
+            .split(',')
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect(),
+        timestamp: fields[10].parse().ok()?,
+        mods: fields.get(11).map_or_else(Mods::default, |acronyms| Mods::from_acronyms(acronyms)),
+    };
+
+    // v1: No Fail was the no_death_penalty setting
+    if version < 2 && score.settings.iter().any(|name| name == "no_death_penalty") {
+        score.settings.retain(|name| name != "no_death_penalty");
+        score.mods.toggle(Mod::NoFail);
+    }
+
+    Some((key, score))
+}
+
+/// How long ago a timestamp was, e.g. "3 days ago"
+pub fn format_age(timestamp: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let seconds = now.saturating_sub(timestamp);
+
+    let (amount, unit) = match seconds {
+        0..=59 => return "just now".to_string(),
+        60..=3599 => (seconds / 60, "minute"),
+        3600..=86399 => (seconds / 3600, "hour"),
+        86400..=2591999 => (seconds / 86400, "day"),
+        2592000..=31535999 => (seconds / 2592000, "month"),
+        _ => (seconds / 31536000, "year"),
+    };
+    format!("{amount} {unit}{} ago", if amount == 1 { "" } else { "s" })
+}
+//endregion
+
+fn load_high_scores_system(mut commands: Commands) {
+    let path = user_dirs::data_dir().join(SCORES_FILE_NAME);
+
+    let high_scores = match HighScores::load(&path) {
+        Ok(high_scores) => high_scores,
+        Err(error) => {
+            // Never overwrite a file that couldn't be read, it may still be recoverable
+            error!("Could not read the scores in {}: {error}", path.display());
+            HighScores {
+                writable: false,
+                ..HighScores::empty(&path)
+            }
+        }
+    };
+
+    commands.insert_resource(high_scores);
+}
+
+fn record_high_score_system(
+    mut high_scores: ResMut<HighScores>,
+    mut recorded_score: ResMut<RecordedScore>,
+    score: Res<ScoreState>,
+    settings: Res<GameSettings>,
+    mods: Res<Mods>,
+    selected_beatmap: Res<SelectedBeatmap>,
+) {
+    let key = BeatmapKey::from(&selected_beatmap.0);
+    recorded_score.rank = high_scores.add(key, HighScore::new(&score, &settings, &mods));
+
+    if let Err(error) = high_scores.save() {
+        error!("Could not save the scores: {error}");
+    }
+}
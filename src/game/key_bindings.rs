@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+//region Actions
+/// Everything the player can do with the keyboard or a gamepad
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Jump,
+    FastFall,
+    MoveLeft,
+    MoveRight,
+    DashUp,
+    DashDown,
+    DashLeft,
+    DashRight,
+    Pause,
+    Retry,
+}
+
+impl Action {
+    /// In the order they are listed in the controls menu and the config file
+    pub const ALL: [Action; 10] = [
+        Action::Jump,
+        Action::FastFall,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::DashUp,
+        Action::DashDown,
+        Action::DashLeft,
+        Action::DashRight,
+        Action::Pause,
+        Action::Retry,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Jump => "Jump",
+            Action::FastFall => "Fast Fall",
+            Action::MoveLeft => "Move Left",
+            Action::MoveRight => "Move Right",
+            Action::DashUp => "Dash Up",
+            Action::DashDown => "Dash Down",
+            Action::DashLeft => "Dash Left",
+            Action::DashRight => "Dash Right",
+            Action::Pause => "Pause",
+            Action::Retry => "Retry",
+        }
+    }
+
+    /// The name of the action in the config file, e.g. bind_jump and pad_jump
+    pub fn id(&self) -> &'static str {
+        match self {
+            Action::Jump => "jump",
+            Action::FastFall => "fast_fall",
+            Action::MoveLeft => "move_left",
+            Action::MoveRight => "move_right",
+            Action::DashUp => "dash_up",
+            Action::DashDown => "dash_down",
+            Action::DashLeft => "dash_left",
+            Action::DashRight => "dash_right",
+            Action::Pause => "pause",
+            Action::Retry => "retry",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Action> {
+        Action::ALL.iter().copied().find(|action| action.id() == id)
+    }
+
+    fn default_keys(&self) -> Vec<KeyCode> {
+        vec![match self {
+            Action::Jump => KeyCode::W,
+            Action::FastFall => KeyCode::S,
+            Action::MoveLeft => KeyCode::A,
+            Action::MoveRight => KeyCode::D,
+            Action::DashUp => KeyCode::Up,
+            Action::DashDown => KeyCode::Down,
+            Action::DashLeft => KeyCode::Left,
+            Action::DashRight => KeyCode::Right,
+            Action::Pause => KeyCode::Escape,
+            // Like osu!'s quick retry
+            Action::Retry => KeyCode::Grave,
+        }]
+    }
+}
+//endregion
+
+//region Key Bindings
+/// The keys bound to each action, saved in the config file. An action can have any number of keys
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyBindings(HashMap<Action, Vec<KeyCode>>);
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings(
+            Action::ALL
+                .iter()
+                .map(|action| (*action, action.default_keys()))
+                .collect(),
+        )
+    }
+}
+
+impl KeyBindings {
+    pub fn keys(&self, action: Action) -> &[KeyCode] {
+        match self.0.get(&action) {
+            Some(keys) => keys,
+            None => &[],
+        }
+    }
+
+    pub fn set_keys(&mut self, action: Action, keys: Vec<KeyCode>) {
+        self.0.insert(action, keys);
+    }
+
+    /// Bind one more key to an action. The key is unbound from any other action first,
+    /// which are returned so the conflict can be shown
+    pub fn bind(&mut self, action: Action, key: KeyCode) -> Vec<Action> {
+        let conflicts = self.conflicts(action, key);
+        for other in &conflicts {
+            self.0.entry(*other).or_default().retain(|bound| *bound != key);
+        }
+
+        let keys = self.0.entry(action).or_default();
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+        conflicts
+    }
+
+    /// The other actions the key is bound to
+    pub fn conflicts(&self, action: Action, key: KeyCode) -> Vec<Action> {
+        Action::ALL
+            .iter()
+            .copied()
+            .filter(|other| *other != action && self.keys(*other).contains(&key))
+            .collect()
+    }
+
+    pub fn pressed(&self, keyboard: &Input<KeyCode>, action: Action) -> bool {
+        self.keys(action).iter().any(|key| keyboard.pressed(*key))
+    }
+
+    pub fn just_pressed(&self, keyboard: &Input<KeyCode>, action: Action) -> bool {
+        self.keys(action).iter().any(|key| keyboard.just_pressed(*key))
+    }
+
+    /// Keys are written with their KeyCode names, e.g. "W, Space"
+    pub fn key_names(&self, action: Action) -> String {
+        self.keys(action)
+            .iter()
+            .map(|key| format!("{key:?}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+pub fn key_code_from_name(name: &str) -> Option<KeyCode> {
+    BINDABLE_KEYS
+        .iter()
+        .copied()
+        .find(|key| format!("{key:?}") == name)
+}
+
+/// Every key that can be bound. KeyCode can't be parsed, so names are matched against these.
+/// F1-F5 (mods), F10 (controls menu) and F11 (fullscreen) are left out since they are always
+/// hardwired. Escape cancels a rebind in the controls menu, so it is only listed to be read back
+/// as Pause's default, and can't be captured
+pub const BINDABLE_KEYS: &[KeyCode] = {
+    use KeyCode::*;
+    &[
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+        Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+        F6, F7, F8, F9, F12,
+        Up, Down, Left, Right,
+        Space, Return, Escape, Tab, Back, Insert, Delete, Home, End, PageUp, PageDown,
+        LShift, RShift, LControl, RControl, LAlt, RAlt,
+        Comma, Period, Slash, Semicolon, Apostrophe, LBracket, RBracket, Backslash, Minus,
+        Equals, Grave,
+        Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8,
+        Numpad9,
+    ]
+};
+//endregion
+
+//region Gamepad Bindings
+/// The gamepad buttons bound to each action, saved in the config file next to the keys.
+/// Moving and dashing can also be done with the sticks, see controls::joystick_control_system
+#[derive(Debug, Clone, PartialEq)]
+pub struct GamepadBindings {
+    buttons: HashMap<Action, Vec<GamepadButtonType>>,
+    /// Dash in the direction of the left stick
+    pub dash: Vec<GamepadButtonType>,
+    /// How far (0.0-1.0) the sticks have to be pushed before they do anything
+    pub deadzone: f32,
+}
+
+impl Default for GamepadBindings {
+    fn default() -> Self {
+        let buttons = |action| match action {
+            Action::Jump => vec![GamepadButtonType::South],
+            Action::FastFall => vec![GamepadButtonType::DPadDown],
+            Action::MoveLeft => vec![GamepadButtonType::DPadLeft],
+            Action::MoveRight => vec![GamepadButtonType::DPadRight],
+            // Dashes are aimed with the sticks
+            Action::DashUp | Action::DashDown | Action::DashLeft | Action::DashRight => vec![],
+            Action::Pause => vec![GamepadButtonType::Start],
+            Action::Retry => vec![GamepadButtonType::Select],
+        };
+
+        GamepadBindings {
+            buttons: Action::ALL
+                .iter()
+                .map(|action| (*action, buttons(*action)))
+                .collect(),
+            dash: vec![GamepadButtonType::RightTrigger2, GamepadButtonType::West],
+            deadzone: 0.2,
+        }
+    }
+}
+
+impl GamepadBindings {
+    pub fn buttons(&self, action: Action) -> &[GamepadButtonType] {
+        match self.buttons.get(&action) {
+            Some(buttons) => buttons,
+            None => &[],
+        }
+    }
+
+    pub fn set_buttons(&mut self, action: Action, buttons: Vec<GamepadButtonType>) {
+        self.buttons.insert(action, buttons);
+    }
+
+    /// Bind one more button to an action, unbinding it from the other actions like KeyBindings::bind
+    pub fn bind(&mut self, action: Action, button: GamepadButtonType) -> Vec<Action> {
+        let conflicts: Vec<Action> = Action::ALL
+            .iter()
+            .copied()
+            .filter(|other| *other != action && self.buttons(*other).contains(&button))
+            .collect();
+        for other in &conflicts {
+            self.buttons.entry(*other).or_default().retain(|bound| *bound != button);
+        }
+        self.dash.retain(|bound| *bound != button);
+
+        let buttons = self.buttons.entry(action).or_default();
+        if !buttons.contains(&button) {
+            buttons.push(button);
+        }
+        conflicts
+    }
+
+    pub fn pressed(&self, input: &Input<GamepadButton>, gamepad: Gamepad, action: Action) -> bool {
+        any_button(self.buttons(action), gamepad, |button| input.pressed(button))
+    }
+
+    pub fn just_pressed(&self, input: &Input<GamepadButton>, gamepad: Gamepad, action: Action) -> bool {
+        any_button(self.buttons(action), gamepad, |button| input.just_pressed(button))
+    }
+
+    pub fn dash_just_pressed(&self, input: &Input<GamepadButton>, gamepad: Gamepad) -> bool {
+        any_button(&self.dash, gamepad, |button| input.just_pressed(button))
+    }
+
+    /// Buttons are written with their GamepadButtonType names, e.g. "South, DPadUp"
+    pub fn button_names(&self, action: Action) -> String {
+        gamepad_button_names(self.buttons(action))
+    }
+}
+
+fn any_button(
+    buttons: &[GamepadButtonType],
+    gamepad: Gamepad,
+    is_down: impl Fn(GamepadButton) -> bool,
+) -> bool {
+    buttons
+        .iter()
+        .any(|button_type| is_down(GamepadButton::new(gamepad, *button_type)))
+}
+
+pub fn gamepad_button_names(buttons: &[GamepadButtonType]) -> String {
+    buttons
+        .iter()
+        .map(|button| format!("{button:?}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+pub fn gamepad_button_from_name(name: &str) -> Option<GamepadButtonType> {
+    BINDABLE_GAMEPAD_BUTTONS
+        .iter()
+        .copied()
+        .find(|button| format!("{button:?}") == name)
+}
+
+/// Every gamepad button that can be bound, to read their names back like BINDABLE_KEYS
+pub const BINDABLE_GAMEPAD_BUTTONS: &[GamepadButtonType] = {
+    use GamepadButtonType::*;
+    &[
+        South, East, North, West, C, Z,
+        LeftTrigger, LeftTrigger2, RightTrigger, RightTrigger2,
+        Select, Start, Mode, LeftThumb, RightThumb,
+        DPadUp, DPadDown, DPadLeft, DPadRight,
+    ]
+};
+//endregion
@@ -0,0 +1,167 @@
+use bevy::asset::LoadState;
+use bevy::prelude::*;
+
+use crate::GameStates;
+
+use super::beatmap_library::SelectedBeatmap;
+use super::beatmap_plugin::BeatmapLoadError;
+use super::ui_plugin::button_text;
+use super::{despawn_with, FontHandles};
+
+//region Plugin Boilerplate
+pub struct LoadingPlugin;
+
+impl Plugin for LoadingPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(LoadingAssets::default())
+            .add_system_set(
+                SystemSet::on_enter(GameStates::Loading).with_system(loading_screen_setup_system),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameStates::Loading).with_system(loading_progress_system),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameStates::Loading)
+                    .with_system(despawn_with::<LoadingScreenEntity>)
+                    .with_system(leave_loading_system),
+            );
+    }
+}
+//endregion
+
+//region Loading Resources and Components
+/// Every asset the game needs before it can start. The systems that load them on entering
+/// GameStates::Loading add their handles here, and the game starts once all of them are loaded
+#[derive(Default)]
+pub struct LoadingAssets(Vec<HandleUntyped>);
+
+impl LoadingAssets {
+    pub fn track<T: Asset>(&mut self, handle: &Handle<T>) {
+        self.0.push(handle.clone_untyped());
+    }
+}
+
+#[derive(Component)]
+struct LoadingScreenEntity;
+
+#[derive(Component)]
+struct LoadingProgressBar;
+
+#[derive(Component)]
+struct LoadingProgressText;
+//endregion
+
+fn loading_screen_setup_system(
+    mut commands: Commands,
+    fonts: Res<FontHandles>,
+    selected: Res<SelectedBeatmap>,
+) {
+    let font = fonts.rubik_regular.clone();
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+            },
+            color: UiColor(Color::rgba(0., 0., 0., 0.0)),
+            ..Default::default()
+        })
+        .insert(LoadingScreenEntity)
+        .with_children(|parent| {
+            parent.spawn_bundle(button_text(
+                Color::WHITE,
+                &font,
+                &format!(
+                    "{} - {} [{}]",
+                    selected.0.artist, selected.0.title, selected.0.version
+                ),
+            ));
+
+            // Progress bar, like the HP bar of the game
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(400.), Val::Px(16.)),
+                        margin: UiRect::all(Val::Px(10.)),
+                        padding: UiRect::all(Val::Px(2.)),
+                        ..Default::default()
+                    },
+                    color: UiColor(Color::rgba(0., 0., 0., 0.6)),
+                    ..Default::default()
+                })
+                .with_children(|parent| {
+                    parent
+                        .spawn_bundle(NodeBundle {
+                            style: Style {
+                                size: Size::new(Val::Percent(0.), Val::Percent(100.)),
+                                ..Default::default()
+                            },
+                            color: UiColor(Color::WHITE),
+                            ..Default::default()
+                        })
+                        .insert(LoadingProgressBar);
+                });
+
+            parent
+                .spawn_bundle(button_text(Color::GRAY, &font, ""))
+                .insert(LoadingProgressText);
+        });
+}
+
+fn loading_progress_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    loading_assets: Res<LoadingAssets>,
+    load_error: Option<Res<BeatmapLoadError>>,
+    screen: Query<Entity, With<LoadingScreenEntity>>,
+    mut progress_bar: Query<&mut Style, With<LoadingProgressBar>>,
+    mut progress_text: Query<&mut Text, With<LoadingProgressText>>,
+    mut game_state: ResMut<State<GameStates>>,
+) {
+    // beatmap_plugin shows the error until the player goes back
+    if load_error.is_some() {
+        screen.for_each(|entity| commands.entity(entity).despawn_recursive());
+        return;
+    }
+
+    let mut loaded = 0;
+    let mut failed = Vec::new();
+    for handle in &loading_assets.0 {
+        match asset_server.get_load_state(handle) {
+            LoadState::Loaded => loaded += 1,
+            LoadState::Failed => failed.push(
+                asset_server
+                    .get_handle_path(handle)
+                    .map_or_else(|| format!("{:?}", handle.id), |path| path.path().display().to_string()),
+            ),
+            _ => {}
+        }
+    }
+
+    if !failed.is_empty() {
+        error!("Could not load the beatmap: {}", failed.join(", "));
+        commands.insert_resource(BeatmapLoadError::FailedAssets(failed));
+        return;
+    }
+
+    let total = loading_assets.0.len();
+    for mut style in progress_bar.iter_mut() {
+        style.size.width = Val::Percent(if total == 0 { 100. } else { loaded as f32 / total as f32 * 100. });
+    }
+    for mut text in progress_text.iter_mut() {
+        text.sections[0].value = format!("Loading {loaded}/{total}");
+    }
+
+    if loaded == total {
+        game_state.overwrite_set(GameStates::Game).unwrap();
+    }
+}
+
+fn leave_loading_system(mut loading_assets: ResMut<LoadingAssets>) {
+    // The next beatmap starts loading from scratch
+    loading_assets.0.clear();
+}
@@ -1,19 +1,41 @@
 use bevy::ecs::schedule::ShouldRun;
 use bevy::prelude::*;
-use crate::game::common_components::MainCamera;
-use crate::game::common_systems::RestartEvent;
-use crate::game::controls::{Dash, MouseCoordinates, Movement};
-use crate::{GameStates, killall_system};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use crate::game::beatmap_library::SelectedBeatmap;
+use crate::game::common_components::{GameEntity, MainCamera};
+use crate::game::common_systems::{PhysicsClock, RestartEvent};
+use crate::game::controls::{Dash, MouseCoordinates, Movement, PlayerInput};
+use crate::game::health_plugin::Health;
+use crate::game::loading_plugin::LoadingAssets;
+use crate::game::mods::Mods;
+use crate::game::scoring::ScoreState;
+use crate::GameStates;
 
 //region Import Modules
-mod beatmap_plugin;
+pub mod beatmap_library;
+pub mod beatmap_plugin;
 mod common_components;
 mod common_systems;
+pub mod config;
 mod controls;
+mod controls_menu_plugin;
 mod fruit_plugin;
-mod osu_reader;
+mod health_plugin;
+pub mod high_scores;
+mod key_bindings;
+mod loading_plugin;
+mod mods;
+pub mod osu_reader;
+mod osz_import;
+mod pause_menu;
 mod player_plugin;
-mod ui_plugin;
+mod results_plugin;
+mod scoring;
+pub mod song_select_plugin;
+mod trajectory;
+pub mod ui_plugin;
+mod user_dirs;
 //endregion
 
 //region Consts
@@ -35,19 +57,62 @@ const PLAYER_SIZE: Vec2 = Vec2::new(600. * PLAYER_SCALE.x, 600. * PLAYER_SCALE.y
 
 // Fruits
 // Air
-const FRUIT_SPEED: f32 = 20.;
+/// Fruits can be cut once their vertical speed drops to this
+const FRUIT_CUTTABLE_SPEED: f32 = 2.;
 const FRUITS_GRAVITY_UP: f32 = 0.6;
 const FRUITS_GRAVITY_HOLD: f32 = 0.168;
 const FRUITS_GRAVITY_FALL: f32 = 1.2;
 // Beatmap
 const BEATMAP_INITIAL_WAIT_TIME: f32 = 0.5;
 const BEATMAP_MUSIC_OFFSET_TIME: f32 = 0.7;
-const BEATMAP_FILE_NAME: &str = "beatMARIO_-_Night_of_Knights_alacat_Hard.osu";
+/// How far (in milliseconds) the song clock may drift from the audio before it jumps to it
+const SONG_CLOCK_MAX_DRIFT: f32 = 100.;
+/// How much of the drift is corrected each time the audio reports its position (0.0-1.0)
+const SONG_CLOCK_CORRECTION: f32 = 0.2;
+const ASSETS_PATH: &str = "assets";
+/// Where imported beatmap sets are extracted to, one folder per set
+pub const BEATMAP_LIBRARY_PATH: &str = "assets/beatmaps";
 /// How much of the screen's horizontal width is spawnable for fruits (0.0-1.0)
 const EFFECTIVE_SCREEN_WIDTH_PERCENT: f32 = 0.9;
+/// How much of the screen's vertical height fruits can be cut in (0.0-1.0)
+const EFFECTIVE_SCREEN_HEIGHT_PERCENT: f32 = 0.8;
+/// Height of the osu! playfield, which HitObject positions are relative to
+const OSU_PLAYFIELD_HEIGHT: f32 = 384.;
 // Fruit Part
 const NUMBER_OF_FRUIT_PIECES: i32 = 4; // Has to be a perfect square
 const MAX_FRUIT_PIECE_SPEED: f32 = 8.;
+// Scoring
+/// Every this many combo, cuts are worth one more time their base score
+const COMBO_SCORE_DIVISOR: u64 = 25;
+// HP (the bar goes from 0.0 to 1.0)
+/// HP drained every second, for each point of the beatmap's HPDrainRate
+const HP_DRAIN_PER_SECOND: f32 = 0.006;
+const HP_RECOVERY_PERFECT: f32 = 0.05;
+const HP_RECOVERY_GREAT: f32 = 0.03;
+const HP_RECOVERY_GOOD: f32 = 0.01;
+const HP_MISS_PENALTY: f32 = 0.04;
+const HP_FALL_PENALTY: f32 = 0.1;
+/// Extra HP lost on misses and falls, for each point of the beatmap's HPDrainRate
+const HP_PENALTY_PER_DRAIN_RATE: f32 = 0.01;
+// Results
+/// Seconds between the end of the last HitObject and the results screen
+const RESULTS_DELAY: f32 = 1.5;
+/// How many bars the hit error histogram of the results screen has (odd, so one is centered on 0ms)
+const HIT_ERROR_HISTOGRAM_BINS: usize = 25;
+// High scores
+/// How many scores are kept for each difficulty
+const HIGH_SCORES_KEPT: usize = 20;
+/// How many scores the song select and results screens list
+const HIGH_SCORES_SHOWN: usize = 5;
+// Seconds counted down before the game resumes from the pause menu
+const PAUSE_RESUME_COUNTDOWN: f32 = 3.;
+
+// Physics
+// The game is simulated in fixed steps, so it plays the same at any frame rate.
+// Velocities are in pixels per 1/60th of a second, and the player's friction is tuned per step
+const PHYSICS_TIMESTEP: f64 = 1. / 60.;
+const PHYSICS_STEP_SCALE: f32 = 60. * PHYSICS_TIMESTEP as f32; // Velocity to pixels moved in one step
+const MAX_PHYSICS_STEPS_PER_FRAME: u32 = 8; // After a long frame the rest of the steps run over the next frames
 
 // Player variables
 // Air
@@ -65,6 +130,9 @@ const JUMP_OFF_WALL_SPEED_ATTRITION: f32 = 5.;
 const DASH_DURATION: f32 = 0.066; // The duration of a dash in seconds
 const MAX_PLAYER_DASHES_MIDAIR: usize = 1;
 const DASH_SPEED: f32 = 60.;
+// Gamepad
+const GAMEPAD_DASH_THRESHOLD: f32 = 0.6; // How far the right stick is flicked to dash
+const GAMEPAD_FAST_FALL_THRESHOLD: f32 = 0.7; // How far down the left stick is pushed to fast fall
 //endregion
 
 //endregion
@@ -79,44 +147,85 @@ struct TexturesHandles {
     aura: Handle<Image>,
 }
 
-struct FontHandles {
-    rubik_regular: Handle<Font>,
+/// The randomness of a run (which fruit, how the pieces fly), seeded by the beatmap so
+/// replaying it with the same inputs gives the same result
+pub struct GameRng {
+    seed: u64,
+    pub rng: StdRng,
 }
 
-struct KeyboardControls {
-    up: Vec<KeyCode>,
-    down: Vec<KeyCode>,
-    right: Vec<KeyCode>,
-    left: Vec<KeyCode>,
+impl GameRng {
+    fn from_beatmap_hash(hash: &str) -> Self {
+        let seed = u64::from_str_radix(hash.get(..16).unwrap_or(hash), 16).unwrap_or_default();
+        GameRng {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.rng = StdRng::seed_from_u64(self.seed);
+    }
+}
+
+pub struct FontHandles {
+    pub rubik_regular: Handle<Font>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioSettings {
+    /// Volumes go from 0.0 to 1.0, the music plays at master_volume * music_volume
+    pub master_volume: f64,
+    pub music_volume: f64,
+    /// Milliseconds the gameplay is delayed by, to make up for audio latency (negative moves it earlier)
+    pub global_offset: f64,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        AudioSettings {
+            master_volume: 1.,
+            music_volume: 0.8,
+            global_offset: 0.,
+        }
+    }
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct GameSettings {
     pub dash_stop : bool,
     pub snap_on_cut : bool,
-    pub no_death_penalty : bool
+    pub dash_scheme : DashScheme,
 }
 
 impl Default for GameSettings {
     fn default() -> Self {
-        Self { dash_stop: false, snap_on_cut: false, no_death_penalty: false }
+        Self { dash_stop: false, snap_on_cut: false, dash_scheme: DashScheme::Arrows }
     }
 }
 
-pub struct Score(pub usize);
+/// How the player aims their dashes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DashScheme {
+    /// The dash keys, in 8 directions
+    Arrows,
+    /// Clicking dashes from the player toward the cursor, in any direction
+    Mouse,
+}
 
-impl KeyboardControls {
-    pub fn is_pressed(kb: &Res<Input<KeyCode>>, keys: &Vec<KeyCode>) -> bool {
-        keys.iter().any(|x| kb.pressed(*x))
-    }
-    pub fn is_just_pressed(kb: &Res<Input<KeyCode>>, keys: &Vec<KeyCode>) -> bool {
-        keys.iter().any(|x| kb.just_pressed(*x))
+impl GameSettings {
+    /// Names of the toggles that are on, recorded with high scores
+    pub fn enabled(&self) -> Vec<&'static str> {
+        [
+            ("dash_stop", self.dash_stop),
+            ("snap_on_cut", self.snap_on_cut),
+            ("mouse_dash", self.dash_scheme == DashScheme::Mouse),
+        ]
+        .into_iter()
+        .filter_map(|(name, enabled)| enabled.then_some(name))
+        .collect()
     }
 }
-
-// There are, as of now, two different functions that run in parallel when the loading GameState is active
-// They have to wait for each other, and this resource is used to make sure everything is set when the game
-// Starts
-pub struct SectionsLoaded( pub usize ); 
 //endregion
 
 //region Main Plugin Definition
@@ -125,32 +234,44 @@ pub struct MainPlugin;
 impl Plugin for MainPlugin {
     fn build(&self, app: &mut App) {
         app
+            .add_startup_system(load_fonts_system)
+            .add_startup_system(spawn_camera_system)
             .add_system_set(
                 SystemSet::on_enter(GameStates::Loading) // Startup systems
                     .with_system(setup_system)
-                    .with_system(killall_system)
-            )
-            .add_system_set(
-                SystemSet::on_update(GameStates::Loading)
-                .with_system(update_loading_screen)
             )
-            .insert_resource(SectionsLoaded ( 0 ))
+            .insert_resource(Mods::default())
+            .add_plugin(config::ConfigPlugin)
             .add_plugin(common_systems::CommonSystems)
+            .add_plugin(loading_plugin::LoadingPlugin)
             .add_plugin(controls::ControlsPlugin)
             .add_plugin(ui_plugin::UIPlugin)
             .add_plugin(beatmap_plugin::BeatmapPlugin)
+            .add_plugin(osz_import::OszImportPlugin)
+            .add_plugin(song_select_plugin::SongSelectPlugin)
             .add_plugin(player_plugin::PlayerPlugin)
             .add_plugin(fruit_plugin::FruitPlugin)
+            .add_plugin(health_plugin::HealthPlugin)
+            .add_plugin(results_plugin::ResultsPlugin)
+            .add_plugin(high_scores::HighScoresPlugin)
+            .add_plugin(controls_menu_plugin::ControlsMenuPlugin)
+            .add_plugin(pause_menu::PauseMenuPlugin)
 
             .add_system_set(
                 SystemSet::on_exit(GameStates::Game) // Startup systems
                     .with_system(leave_game_system)
-                    .with_system(killall_system)
+                    .with_system(despawn_with::<GameEntity>)
             );
     }
 }
 //endregion
 
+/// Despawn the entities tagged with a state's marker component when leaving it.
+/// Every state tags what it spawns, so the entities of the other states (e.g. the game under the pause menu) are kept
+pub fn despawn_with<T: Component>(mut commands: Commands, query: Query<Entity, With<T>>) {
+    query.for_each(|entity| commands.entity(entity).despawn_recursive());
+}
+
 pub fn is_game_state_criteria(
     game_state: Res<State<GameStates>>,
 ) -> ShouldRun {
@@ -166,16 +287,11 @@ fn setup_system(
     asset_server: Res<AssetServer>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
     mut restart_events: EventWriter<RestartEvent>,
-    mut sections_loaded: ResMut<SectionsLoaded>,
+    mut loading_assets: ResMut<LoadingAssets>,
+    fonts: Res<FontHandles>,
+    mods: Res<Mods>,
+    selected: Res<SelectedBeatmap>,
 ) {
-    // Spawn camera
-    commands
-        .spawn_bundle(Camera2dBundle {
-            transform: Transform::from_xyz(0., 0., 10.),
-            ..Camera2dBundle::new_with_far(100.0)
-        })
-        .insert(MainCamera);
-
     //region Add asset handles
     let mut fruits_pieces_texture_atlas = Vec::new();
     
@@ -191,7 +307,7 @@ fn setup_system(
         fruits_pieces_texture_atlas.push(texture_atlases.add(texture_atlas));
     });
 
-    commands.insert_resource(TexturesHandles {
+    let textures = TexturesHandles {
         fruits: FRUIT_ASSETS_PATH
             .iter()
             .map(|x| asset_server.load(*x))
@@ -200,44 +316,62 @@ fn setup_system(
         ninja: asset_server.load(NINJA_PATH),
         aim: asset_server.load(AIM_PATH),
         aura: asset_server.load(AURA_PATH),
-    });
+    };
+
+    // The game starts once these are loaded (the atlases use the fruits' images)
+    textures.fruits.iter().for_each(|fruit| loading_assets.track(fruit));
+    loading_assets.track(&textures.ninja);
+    loading_assets.track(&textures.aim);
+    loading_assets.track(&textures.aura);
+    loading_assets.track(&fonts.rubik_regular);
+
+    commands.insert_resource(textures);
 
-    commands.insert_resource(
-        FontHandles {
-            rubik_regular: asset_server.load("fonts/Rubik-Regular.ttf"),
-        }
-    );
     //endregion
 
     // mod.rs resources
-    commands.insert_resource(Score(0));
-    commands.insert_resource(GameSettings::default());
+    commands.insert_resource(ScoreState::new(mods.score_multiplier()));
+    commands.insert_resource(Health::default());
+    commands.insert_resource(GameRng::from_beatmap_hash(&selected.0.hash));
+    commands.insert_resource(PhysicsClock::default());
 
     // ControlsPlugin resources
     commands.insert_resource(Movement::default());
     commands.insert_resource(MouseCoordinates::default());
     commands.insert_resource(Dash::default());
+    commands.insert_resource(PlayerInput::default());
 
     // Restart events
     restart_events.send_default();
 
     // BeatmapPlugin has its own init system
+}
 
-    // Tell the loading screen that this section is loaded
-    sections_loaded.0 += 1;
+// One camera for every state, none of them despawns it
+fn spawn_camera_system(mut commands: Commands) {
+    commands
+        .spawn_bundle(Camera2dBundle {
+            transform: Transform::from_xyz(0., 0., 10.),
+            ..Camera2dBundle::new_with_far(100.0)
+        })
+        .insert(MainCamera);
 }
 
-fn update_loading_screen(mut game_state: ResMut<State<GameStates>>, sections_loaded: Res<SectionsLoaded>) {
-    if sections_loaded.0 == 2 {
-        game_state.overwrite_set(GameStates::Game).unwrap();
-    }
+// The menus need the font before anything is loaded
+fn load_fonts_system(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(
+        FontHandles {
+            rubik_regular: asset_server.load("fonts/Rubik-Regular.ttf"),
+        }
+    );
 }
 
 fn leave_game_system(
     mut commands: Commands
 ) {
     // Delete mod.rs resources
-    commands.remove_resource::<Score>();
+    // ScoreState is kept for the results screen, setup_system replaces it on the next run
+    commands.remove_resource::<Health>();
 
     // Delete ControlsPlugin resources
     commands.remove_resource::<Movement>();
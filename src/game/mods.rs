@@ -0,0 +1,168 @@
+use std::fmt;
+
+//region Mods
+/// Gameplay modifiers, chosen in the song select like osu!'s mods
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mod {
+    Easy,
+    HardRock,
+    HalfTime,
+    DoubleTime,
+    NoFail,
+}
+
+impl Mod {
+    /// In the order they are shown and written in
+    pub const ALL: [Mod; 5] = [
+        Mod::Easy,
+        Mod::HardRock,
+        Mod::HalfTime,
+        Mod::DoubleTime,
+        Mod::NoFail,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mod::Easy => "Easy",
+            Mod::HardRock => "Hard Rock",
+            Mod::HalfTime => "Half Time",
+            Mod::DoubleTime => "Double Time",
+            Mod::NoFail => "No Fail",
+        }
+    }
+
+    pub fn acronym(&self) -> &'static str {
+        match self {
+            Mod::Easy => "EZ",
+            Mod::HardRock => "HR",
+            Mod::HalfTime => "HT",
+            Mod::DoubleTime => "DT",
+            Mod::NoFail => "NF",
+        }
+    }
+
+    /// The same multipliers as osu!
+    pub fn score_multiplier(&self) -> f64 {
+        match self {
+            Mod::Easy => 0.5,
+            Mod::HardRock => 1.06,
+            Mod::HalfTime => 0.3,
+            Mod::DoubleTime => 1.12,
+            Mod::NoFail => 0.5,
+        }
+    }
+
+    /// The mod that can't be enabled at the same time as this one
+    fn incompatible_with(&self) -> Option<Mod> {
+        match self {
+            Mod::Easy => Some(Mod::HardRock),
+            Mod::HardRock => Some(Mod::Easy),
+            Mod::HalfTime => Some(Mod::DoubleTime),
+            Mod::DoubleTime => Some(Mod::HalfTime),
+            Mod::NoFail => None,
+        }
+    }
+}
+
+/// The enabled mods. Kept between runs, until they are changed in the song select
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Mods(Vec<Mod>);
+
+impl Mods {
+    pub fn is_enabled(&self, game_mod: Mod) -> bool {
+        self.0.contains(&game_mod)
+    }
+
+    /// Enable or disable a mod, disabling the one it is incompatible with
+    pub fn toggle(&mut self, game_mod: Mod) {
+        if self.is_enabled(game_mod) {
+            self.0.retain(|other| *other != game_mod);
+            return;
+        }
+
+        if let Some(incompatible) = game_mod.incompatible_with() {
+            self.0.retain(|other| *other != incompatible);
+        }
+        self.0.push(game_mod);
+        self.0
+            .sort_by_key(|game_mod| Mod::ALL.iter().position(|other| other == game_mod));
+    }
+
+    pub fn score_multiplier(&self) -> f64 {
+        self.0.iter().map(Mod::score_multiplier).product()
+    }
+
+    /// How fast the music and the beatmap are played
+    pub fn playback_rate(&self) -> f64 {
+        if self.is_enabled(Mod::DoubleTime) {
+            1.5
+        } else if self.is_enabled(Mod::HalfTime) {
+            0.75
+        } else {
+            1.
+        }
+    }
+
+    /// Multiplies the gravity of fruits, so they fly faster (Hard Rock) or slower (Easy)
+    pub fn gravity_scale(&self) -> f32 {
+        if self.is_enabled(Mod::HardRock) {
+            1.4
+        } else if self.is_enabled(Mod::Easy) {
+            0.7
+        } else {
+            1.
+        }
+    }
+
+    /// Multiplies the size of fruits, which is also how easy they are to hit
+    pub fn fruit_scale(&self) -> f32 {
+        if self.is_enabled(Mod::HardRock) {
+            0.75
+        } else if self.is_enabled(Mod::Easy) {
+            1.3
+        } else {
+            1.
+        }
+    }
+
+    /// The OverallDifficulty the hit windows are made from. Like in osu!, Hard Rock multiplies
+    /// it by 1.4 and Easy halves it
+    pub fn overall_difficulty(&self, overall_difficulty: f32) -> f32 {
+        if self.is_enabled(Mod::HardRock) {
+            (overall_difficulty * 1.4).min(10.)
+        } else if self.is_enabled(Mod::Easy) {
+            overall_difficulty / 2.
+        } else {
+            overall_difficulty
+        }
+    }
+
+    /// Read the acronyms written by Display, e.g. "HRDT". Unknown acronyms are ignored
+    pub fn from_acronyms(acronyms: &str) -> Self {
+        let mut mods = Mods::default();
+        for chunk in acronyms.as_bytes().chunks(2) {
+            let acronym = String::from_utf8_lossy(chunk);
+            if let Some(game_mod) = Mod::ALL.iter().find(|game_mod| game_mod.acronym() == acronym) {
+                if !mods.is_enabled(*game_mod) {
+                    mods.toggle(*game_mod);
+                }
+            }
+        }
+        mods
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// The acronyms of the enabled mods, e.g. "HRDT" (empty if there are none)
+impl fmt::Display for Mods {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for game_mod in &self.0 {
+            write!(f, "{}", game_mod.acronym())?;
+        }
+        Ok(())
+    }
+}
+//endregion
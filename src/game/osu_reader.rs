@@ -1,117 +1,890 @@
-use std::collections::HashMap;
-use std::fs::{write, File};
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write;
+use std::fs::File;
 use std::io::Read;
+use std::str::FromStr;
 
-use bevy::prelude::Vec2;
+use bevy::prelude::{Color, Vec2};
+
+//region Beatmap
+/// Every section of a .osu file, with proper types
+#[derive(Debug, Default)]
+pub struct OsuBeatmap {
+    /// The version in the `osu file format vXX` header
+    pub format_version: u32,
+    pub general: General,
+    pub metadata: Metadata,
+    pub difficulty: Difficulty,
+    pub events: Events,
+    pub timing_points: TimingPoints,
+    pub colours: Colours,
+    pub hit_objects: Vec<HitObject>,
+}
 
 #[derive(Debug)]
-pub enum OsuFileSection {
-    KeyValueMap(HashMap<String, String>),
-    HitObjects(Vec<HitObject>),
-    Events(String),
-    None,
+pub struct General {
+    pub audio_filename: String,
+    /// Milliseconds of silence before the audio starts playing
+    pub audio_lead_in: i32,
+    /// Time in milliseconds where the song preview starts (-1 if not set)
+    pub preview_time: i32,
+    pub countdown: u32,
+    pub sample_set: String,
+    pub stack_leniency: f32,
+    /// 0 = osu!, 1 = osu!taiko, 2 = osu!catch, 3 = osu!mania
+    pub mode: u32,
+    pub letterbox_in_breaks: bool,
+    pub widescreen_storyboard: bool,
+}
+
+impl Default for General {
+    fn default() -> Self {
+        General {
+            audio_filename: String::new(),
+            audio_lead_in: 0,
+            preview_time: -1,
+            countdown: 1,
+            sample_set: "Normal".to_string(),
+            stack_leniency: 0.7,
+            mode: 0,
+            letterbox_in_breaks: false,
+            widescreen_storyboard: false,
+        }
+    }
+}
+
+impl General {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), OsuErrorReason> {
+        match key {
+            "AudioFilename" => self.audio_filename = value.to_string(),
+            "AudioLeadIn" => self.audio_lead_in = parse_value(value)?,
+            "PreviewTime" => self.preview_time = parse_value(value)?,
+            "Countdown" => self.countdown = parse_value(value)?,
+            "SampleSet" => self.sample_set = value.to_string(),
+            "StackLeniency" => self.stack_leniency = parse_value(value)?,
+            "Mode" => self.mode = parse_value(value)?,
+            "LetterboxInBreaks" => self.letterbox_in_breaks = parse_bool(value)?,
+            "WidescreenStoryboard" => self.widescreen_storyboard = parse_bool(value)?,
+            _ => {} // Keys the game doesn't care about
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Metadata {
+    pub title: String,
+    pub title_unicode: String,
+    pub artist: String,
+    pub artist_unicode: String,
+    pub creator: String,
+    /// The difficulty name
+    pub version: String,
+    pub source: String,
+    pub tags: Vec<String>,
+    pub beatmap_id: Option<i64>,
+    pub beatmap_set_id: Option<i64>,
+}
+
+impl Metadata {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), OsuErrorReason> {
+        match key {
+            "Title" => self.title = value.to_string(),
+            "TitleUnicode" => self.title_unicode = value.to_string(),
+            "Artist" => self.artist = value.to_string(),
+            "ArtistUnicode" => self.artist_unicode = value.to_string(),
+            "Creator" => self.creator = value.to_string(),
+            "Version" => self.version = value.to_string(),
+            "Source" => self.source = value.to_string(),
+            "Tags" => self.tags = value.split_whitespace().map(str::to_string).collect(),
+            // Unsubmitted maps use -1 or 0
+            "BeatmapID" => self.beatmap_id = Some(parse_value(value)?).filter(|id| *id > 0),
+            "BeatmapSetID" => self.beatmap_set_id = Some(parse_value(value)?).filter(|id| *id > 0),
+            _ => {}
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
+pub struct Difficulty {
+    pub hp_drain_rate: f32,
+    pub circle_size: f32,
+    pub overall_difficulty: f32,
+    pub approach_rate: f32,
+    /// Base slider velocity in hundreds of osu! pixels per beat
+    pub slider_multiplier: f64,
+    /// Amount of slider ticks per beat
+    pub slider_tick_rate: f64,
+}
+
+impl Default for Difficulty {
+    fn default() -> Self {
+        Difficulty {
+            hp_drain_rate: 5.,
+            circle_size: 5.,
+            overall_difficulty: 5.,
+            approach_rate: 5.,
+            slider_multiplier: 1.4,
+            slider_tick_rate: 1.,
+        }
+    }
+}
+
+impl Difficulty {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), OsuErrorReason> {
+        match key {
+            "HPDrainRate" => self.hp_drain_rate = parse_value(value)?,
+            "CircleSize" => self.circle_size = parse_value(value)?,
+            "OverallDifficulty" => self.overall_difficulty = parse_value(value)?,
+            "ApproachRate" => self.approach_rate = parse_value(value)?,
+            "SliderMultiplier" => self.slider_multiplier = parse_value(value)?,
+            "SliderTickRate" => self.slider_tick_rate = parse_value(value)?,
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Events {
+    /// Background image, relative to the beatmap folder
+    pub background: Option<String>,
+    pub video: Option<String>,
+    pub breaks: Vec<BreakPeriod>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BreakPeriod {
+    pub start_time: i32,
+    pub end_time: i32,
+}
+
+#[derive(Debug, Default)]
+pub struct Colours {
+    /// Combo1, Combo2... in order
+    pub combo_colours: Vec<Color>,
+    pub slider_track_override: Option<Color>,
+    pub slider_border: Option<Color>,
+}
+
+impl Colours {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), OsuErrorReason> {
+        let colour = colour_processing(value)?;
+
+        match key {
+            "SliderTrackOverride" => self.slider_track_override = Some(colour),
+            "SliderBorder" => self.slider_border = Some(colour),
+            _ if key.starts_with("Combo") => self.combo_colours.push(colour),
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+//endregion
+
+//region Hit Objects
+#[derive(Debug, Clone)]
 pub struct HitObject {
-    pub hit_type: usize,
-    pub time: u32,
+    /// Position in osu! pixels (the playfield is 512x384)
     pub position: Vec2,
+    /// Time in milliseconds
+    pub time: u32,
+    /// Whether this object starts a new combo
+    pub new_combo: bool,
+    /// How many combo colours to skip when starting a new combo (0-7)
+    pub combo_colour_skip: u8,
+    pub hit_sound: HitSound,
+    pub kind: HitObjectKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HitObjectKind {
+    Circle,
+    Slider(Slider),
+    Spinner { end_time: u32 },
+    /// osu!mania hold note
+    Hold { end_time: u32 },
 }
 
-fn event_processing(line: &str) -> Option<String> {
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slider {
+    pub curve_type: CurveType,
+    /// Every point of the curve after the start position, in osu! pixels
+    pub control_points: Vec<Vec2>,
+    /// Amount of times the slider is traversed (1 means no repeats)
+    pub slides: u32,
+    /// Visual length in osu! pixels
+    pub length: f64,
+    /// Hit sounds of the head, every repeat and the tail, in order
+    pub edge_sounds: Vec<HitSound>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CurveType {
+    Bezier,
+    CentripetalCatmullRom,
+    Linear,
+    PerfectCircle,
+}
+
+/// The hitSound bit flags of a hit object
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HitSound(pub u8);
+
+impl HitSound {
+    pub fn whistle(&self) -> bool {
+        self.0 & (1 << 1) != 0
+    }
+
+    pub fn finish(&self) -> bool {
+        self.0 & (1 << 2) != 0
+    }
+
+    pub fn clap(&self) -> bool {
+        self.0 & (1 << 3) != 0
+    }
+}
+
+impl HitObject {
+    pub fn is_circle(&self) -> bool {
+        matches!(self.kind, HitObjectKind::Circle)
+    }
+
+    pub fn is_slider(&self) -> bool {
+        matches!(self.kind, HitObjectKind::Slider(_))
+    }
+
+    pub fn is_spinner(&self) -> bool {
+        matches!(self.kind, HitObjectKind::Spinner { .. })
+    }
+
+    pub fn is_hold(&self) -> bool {
+        matches!(self.kind, HitObjectKind::Hold { .. })
+    }
+}
+
+impl OsuBeatmap {
+    /// When `hit_object` ends, in milliseconds.
+    /// Slider durations depend on the timing points, which is why this lives in the beatmap
+    pub fn end_time(&self, hit_object: &HitObject) -> u32 {
+        match &hit_object.kind {
+            HitObjectKind::Circle => hit_object.time,
+            HitObjectKind::Spinner { end_time } | HitObjectKind::Hold { end_time } => *end_time,
+            HitObjectKind::Slider(slider) => {
+                let time = hit_object.time as f64;
+                let beat_length = self.timing_points.beat_length_at(time).unwrap_or(500.);
+                let pixels_per_beat = self.difficulty.slider_multiplier
+                    * 100.
+                    * self.timing_points.slider_velocity_at(time);
+
+                let duration = slider.length / pixels_per_beat * beat_length * slider.slides as f64;
+                hit_object.time + duration.max(0.) as u32
+            }
+        }
+    }
+    /// A human readable summary of everything that was parsed, followed by the full parsed data.
+    /// Meant for diagnosing beatmaps that don't play as expected (see `--dump-beatmap`)
+    pub fn debug_report(&self) -> String {
+        let mut report = String::new();
+        let count = |predicate: fn(&HitObject) -> bool| {
+            self.hit_objects.iter().filter(|&hit_object| predicate(hit_object)).count()
+        };
+
+        // Writing into a String never fails
+        let _ = writeln!(report, "osu file format v{}", self.format_version);
+        let _ = writeln!(
+            report,
+            "{} - {} [{}] by {}",
+            self.metadata.artist, self.metadata.title, self.metadata.version, self.metadata.creator
+        );
+        let _ = writeln!(report, "Audio: {}", self.general.audio_filename);
+        let _ = writeln!(report, "Background: {:?}", self.events.background);
+        let _ = writeln!(
+            report,
+            "HP {} / CS {} / OD {} / AR {}",
+            self.difficulty.hp_drain_rate,
+            self.difficulty.circle_size,
+            self.difficulty.overall_difficulty,
+            self.difficulty.approach_rate
+        );
+
+        let bpms = self
+            .timing_points
+            .0
+            .iter()
+            .filter(|point| point.uninherited)
+            .map(|point| 60_000. / point.beat_length);
+        let min_bpm = bpms.clone().fold(f64::INFINITY, f64::min);
+        let max_bpm = bpms.fold(0., f64::max);
+        let _ = writeln!(
+            report,
+            "Timing points: {} (BPM {min_bpm:.1}-{max_bpm:.1})",
+            self.timing_points.0.len()
+        );
+        let _ = writeln!(report, "Breaks: {}", self.events.breaks.len());
+        let _ = writeln!(
+            report,
+            "Hit objects: {} ({} circles, {} sliders, {} spinners, {} holds)",
+            self.hit_objects.len(),
+            count(HitObject::is_circle),
+            count(HitObject::is_slider),
+            count(HitObject::is_spinner),
+            count(HitObject::is_hold)
+        );
+        if let Some(last) = self.hit_objects.last() {
+            let _ = writeln!(report, "Length: {:.1}s", self.end_time(last) as f32 / 1000.);
+        }
+
+        let _ = write!(report, "\n{self:#?}");
+        report
+    }
+}
+//endregion
+
+//region Errors
+/// Why a beatmap could not be read, and where
+#[derive(Debug)]
+pub struct OsuError {
+    pub file: String,
+    /// 1-based line of the file that caused the error, if any
+    pub line: Option<usize>,
+    /// The [Section] the line belongs to
+    pub section: Option<String>,
+    pub reason: OsuErrorReason,
+}
+
+#[derive(Debug)]
+pub enum OsuErrorReason {
+    Io(std::io::Error),
+    /// A comma-separated line is missing a mandatory field
+    MissingField(&'static str),
+    /// A value could not be parsed into the expected type
+    InvalidValue(String),
+    /// The type of a hit object has none of the circle, slider, spinner or hold bits
+    UnknownHitObjectType(u32),
+}
+
+impl fmt::Display for OsuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file)?;
+        if let Some(line) = self.line {
+            write!(f, ":{line}")?;
+        }
+        if let Some(section) = &self.section {
+            write!(f, " in {section}")?;
+        }
+        write!(f, ": {}", self.reason)
+    }
+}
+
+impl fmt::Display for OsuErrorReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OsuErrorReason::Io(error) => write!(f, "could not read the file ({error})"),
+            OsuErrorReason::MissingField(field) => write!(f, "missing field '{field}'"),
+            OsuErrorReason::InvalidValue(value) => write!(f, "invalid value '{value}'"),
+            OsuErrorReason::UnknownHitObjectType(hit_type) => {
+                write!(f, "unknown hit object type {hit_type}")
+            }
+        }
+    }
+}
+
+impl Error for OsuError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.reason {
+            OsuErrorReason::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+//endregion
+
+//region Timing Points
+/// A single line of the [TimingPoints] section
+///
+/// Uninherited points (the red lines in the osu! editor) define the beat length and meter.
+/// Inherited points (the green lines) only change the slider velocity, volume and effects
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimingPoint {
+    /// Start time of the timing section, in milliseconds
+    pub time: f64,
+    /// For uninherited points, the duration of a beat in milliseconds.
+    /// For inherited points, a negative inverse slider velocity multiplier, as a percentage
+    pub beat_length: f64,
+    /// Amount of beats in a measure
+    pub meter: u32,
+    pub sample_set: SampleSet,
+    pub sample_index: u32,
+    /// Volume percentage for hit objects (0-100)
+    pub volume: u32,
+    pub uninherited: bool,
+    pub effects: TimingEffects,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SampleSet {
+    #[default]
+    BeatmapDefault,
+    Normal,
+    Soft,
+    Drum,
+}
+
+impl SampleSet {
+    fn from_id(id: u32) -> Self {
+        match id {
+            1 => SampleSet::Normal,
+            2 => SampleSet::Soft,
+            3 => SampleSet::Drum,
+            _ => SampleSet::BeatmapDefault,
+        }
+    }
+}
+
+/// The effects bit flags of a [`TimingPoint`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TimingEffects(pub u32);
+
+impl TimingEffects {
+    pub fn kiai(&self) -> bool {
+        self.0 & 1 != 0
+    }
+
+    /// Whether the first barline is omitted (osu!taiko and osu!mania only)
+    pub fn omit_first_barline(&self) -> bool {
+        self.0 & (1 << 3) != 0
+    }
+}
+
+impl TimingPoint {
+    /// The slider velocity multiplier this point sets (always 1.0 for uninherited points)
+    pub fn slider_velocity(&self) -> f64 {
+        if self.uninherited || self.beat_length >= 0. {
+            return 1.;
+        }
+
+        // Negative beat lengths are inverse percentages: -50 means 2x
+        (-100. / self.beat_length).clamp(0.1, 10.)
+    }
+}
+
+/// Every timing point of a beatmap, sorted by time
+#[derive(Debug, Clone, Default)]
+pub struct TimingPoints(pub Vec<TimingPoint>);
+
+impl TimingPoints {
+    /// The uninherited timing point active at `time` (in milliseconds).
+    /// Before the first red line, the first one is used, like osu! does
+    pub fn timing_point_at(&self, time: f64) -> Option<&TimingPoint> {
+        let uninherited = self.0.iter().filter(|point| point.uninherited);
+        let first = uninherited.clone().next();
+
+        uninherited
+            .take_while(|point| point.time <= time)
+            .last()
+            .or(first)
+    }
+
+    /// The latest timing point of any kind active at `time` (in milliseconds)
+    pub fn effect_point_at(&self, time: f64) -> Option<&TimingPoint> {
+        self.0.iter().take_while(|point| point.time <= time).last()
+    }
+
+    /// Duration of a beat at `time`, in milliseconds
+    pub fn beat_length_at(&self, time: f64) -> Option<f64> {
+        self.timing_point_at(time).map(|point| point.beat_length)
+    }
+
+    pub fn bpm_at(&self, time: f64) -> Option<f64> {
+        self.beat_length_at(time).map(|beat_length| 60_000. / beat_length)
+    }
+
+    pub fn meter_at(&self, time: f64) -> Option<u32> {
+        self.timing_point_at(time).map(|point| point.meter)
+    }
+
+    /// Slider velocity multiplier at `time`. Uninherited points reset it to 1.0
+    pub fn slider_velocity_at(&self, time: f64) -> f64 {
+        self.effect_point_at(time)
+            .map_or(1., |point| point.slider_velocity())
+    }
+
+    pub fn is_kiai_at(&self, time: f64) -> bool {
+        self.effect_point_at(time)
+            .is_some_and(|point| point.effects.kiai())
+    }
+
+    /// How many beats (possibly fractional) have passed since the active red line at `time`.
+    /// Useful to sync animations to the music
+    pub fn beat_at(&self, time: f64) -> Option<f64> {
+        self.timing_point_at(time)
+            .map(|point| (time - point.time) / point.beat_length)
+    }
+
+    fn sort(&mut self) {
+        // Stable sort so that, at the same time, the order of the file is kept
+        // (osu! puts red lines before the green lines that override them)
+        self.0.sort_by(|a, b| a.time.total_cmp(&b.time));
+    }
+}
+
+fn timing_point_processing(line: &str) -> Result<TimingPoint, OsuErrorReason> {
+    // Split line into its parts
+    let information: Vec<&str> = line.split(',').map(|part| part.trim()).collect();
+
+    // Only the first two fields are mandatory, old beatmap versions omit the rest
+    let optional = |index: usize, default: u32| -> u32 {
+        information
+            .get(index)
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(default)
+    };
+
+    Ok(TimingPoint {
+        time: parse_value(required(&information, 0, "time")?)?,
+        beat_length: parse_value(required(&information, 1, "beatLength")?)?,
+        meter: optional(2, 4),
+        sample_set: SampleSet::from_id(optional(3, 0)),
+        sample_index: optional(4, 0),
+        volume: optional(5, 100),
+        uninherited: optional(6, 1) == 1,
+        effects: TimingEffects(optional(7, 0)),
+    })
+}
+//endregion
+
+// The sections of the .osu file that the parser understands
+enum Section {
+    None,
+    General,
+    Metadata,
+    Difficulty,
+    Events,
+    TimingPoints,
+    Colours,
+    HitObjects,
+    // [Editor] and anything new that the game doesn't use
+    Ignored,
+}
+
+fn parse_value<T: FromStr>(value: &str) -> Result<T, OsuErrorReason> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| OsuErrorReason::InvalidValue(value.to_string()))
+}
+
+fn parse_bool(value: &str) -> Result<bool, OsuErrorReason> {
+    Ok(parse_value::<u32>(value)? == 1)
+}
+
+/// Get the field at `index` of a comma-separated line, failing if the line is too short
+fn required<'a>(
+    information: &[&'a str],
+    index: usize,
+    name: &'static str,
+) -> Result<&'a str, OsuErrorReason> {
+    information
+        .get(index)
+        .copied()
+        .ok_or(OsuErrorReason::MissingField(name))
+}
+
+fn colour_processing(value: &str) -> Result<Color, OsuErrorReason> {
+    let rgb = value
+        .split(',')
+        .map(parse_value)
+        .collect::<Result<Vec<u8>, _>>()?;
+
+    if rgb.len() < 3 {
+        return Err(OsuErrorReason::InvalidValue(value.to_string()));
+    }
+
+    Ok(Color::rgb_u8(rgb[0], rgb[1], rgb[2]))
+}
+
+fn event_processing(line: &str, events: &mut Events) -> Result<(), OsuErrorReason> {
     // Split line into its parts
     let information: Vec<&str> = line.split(',').collect();
+    let file_name = || -> Result<String, OsuErrorReason> {
+        Ok(required(&information, 2, "filename")?.trim().replace('"', ""))
+    };
 
-    if information[0] == "0" && information[1] == "0" {
-        return Some(information[2].to_string().replace("\"", ""));
+    match information[0] {
+        "0" => events.background = Some(file_name()?),
+        "1" | "Video" => events.video = Some(file_name()?),
+        "2" | "Break" => events.breaks.push(BreakPeriod {
+            start_time: parse_value(required(&information, 1, "startTime")?)?,
+            end_time: parse_value(required(&information, 2, "endTime")?)?,
+        }),
+        _ => {} // Storyboard events
     }
 
-    None
+    Ok(())
+}
+
+fn point_processing(value: &str) -> Result<Vec2, OsuErrorReason> {
+    let (x, y) = value
+        .split_once(':')
+        .ok_or_else(|| OsuErrorReason::InvalidValue(value.to_string()))?;
+
+    Ok(Vec2::new(parse_value(x)?, parse_value(y)?))
+}
+
+fn slider_processing(information: &[&str]) -> Result<Slider, OsuErrorReason> {
+    // curveType|curvePoints,slides,length,edgeSounds,edgeSets
+    let mut curve = required(information, 5, "curve")?.split('|');
+
+    let curve_type = match curve.next().map(str::trim) {
+        Some("B") => CurveType::Bezier,
+        Some("C") => CurveType::CentripetalCatmullRom,
+        Some("L") => CurveType::Linear,
+        Some("P") => CurveType::PerfectCircle,
+        other => return Err(OsuErrorReason::InvalidValue(other.unwrap_or("").to_string())),
+    };
+
+    let control_points = curve
+        .map(point_processing)
+        .collect::<Result<Vec<Vec2>, _>>()?;
+
+    // Edge sounds are optional, and can be empty
+    let edge_sounds = match information.get(8).map(|value| value.trim()) {
+        Some(value) if !value.is_empty() => value
+            .split('|')
+            .map(|sound| parse_value(sound).map(HitSound))
+            .collect::<Result<Vec<HitSound>, _>>()?,
+        _ => vec![],
+    };
+
+    Ok(Slider {
+        curve_type,
+        control_points,
+        slides: parse_value(required(information, 6, "slides")?)?,
+        length: parse_value(required(information, 7, "length")?)?,
+        edge_sounds,
+    })
 }
 
-fn hitobject_processing(line: &str) -> HitObject {
+fn hitobject_processing(line: &str) -> Result<HitObject, OsuErrorReason> {
     // Split line into its parts
+    // x,y,time,type,hitSound,objectParams,hitSample
     let information: Vec<&str> = line.split(',').collect();
 
-    // Potential alternative: creating new variants of the OsuFileInfo Enum for each HitObject type?
-    return HitObject {
+    let hit_type: u32 = parse_value(required(&information, 3, "type")?)?;
+
+    // The type is a set of bit flags
+    let kind = if hit_type & 1 != 0 {
+        HitObjectKind::Circle
+    } else if hit_type & (1 << 1) != 0 {
+        HitObjectKind::Slider(slider_processing(&information)?)
+    } else if hit_type & (1 << 3) != 0 {
+        HitObjectKind::Spinner {
+            end_time: parse_value(required(&information, 5, "endTime")?)?,
+        }
+    } else if hit_type & (1 << 7) != 0 {
+        // Hold notes separate the end time from the hit sample with ':' instead of ','
+        let params = required(&information, 5, "endTime")?;
+        HitObjectKind::Hold {
+            end_time: parse_value(params.split(':').next().unwrap_or(params))?,
+        }
+    } else {
+        return Err(OsuErrorReason::UnknownHitObjectType(hit_type));
+    };
+
+    Ok(HitObject {
         position: Vec2 {
-            x: information[0].parse().unwrap(),
-            y: information[1].parse().unwrap(),
+            x: parse_value(required(&information, 0, "x")?)?,
+            y: parse_value(required(&information, 1, "y")?)?,
         },
-        time: information[2].parse().unwrap(),
-        hit_type: information[3].parse().unwrap(),
-    };
+        time: parse_value(required(&information, 2, "time")?)?,
+        new_combo: hit_type & (1 << 2) != 0,
+        combo_colour_skip: ((hit_type >> 4) & 0b111) as u8,
+        // Some old beatmaps omit the hit sound
+        hit_sound: HitSound(
+            information
+                .get(4)
+                .and_then(|value| value.trim().parse().ok())
+                .unwrap_or(0),
+        ),
+        kind,
+    })
 }
 
-fn json_like_key_value_get(line: &str) -> Option<(String, String)> {
+fn json_like_key_value_get(line: &str) -> Option<(&str, &str)> {
     // Ignore lines that do not follow the 'Json-like' key:value pattern
-    if !line.contains(':') {
-        return None;
-    }
+    // Only the first ':' counts, titles such as "Re:Zero" have more of them
+    let (key, value) = line.split_once(':')?;
 
-    // Remove potential trailing whitespace after ':'
-    let line = line.replace(": ", ":");
-    // Then split line into the parts before and after the ':'
-    let parts: Vec<&str> = line.split(':').collect();
-    Some((parts[0].to_string(), parts[1].to_string()))
+    // Remove potential whitespace around the ':'
+    Some((key.trim(), value.trim()))
 }
 
-pub fn open_osu(path: &str) -> HashMap<String, OsuFileSection> {
+/// Read and parse the .osu file at `path`
+pub fn open_osu(path: &str) -> Result<OsuBeatmap, OsuError> {
     let mut source = String::new();
 
     File::open(path)
-        .expect("Path does not exist!")
-        .read_to_string(&mut source)
-        .unwrap();
+        .and_then(|mut file| file.read_to_string(&mut source))
+        .map_err(|error| OsuError {
+            file: path.to_string(),
+            line: None,
+            section: None,
+            reason: OsuErrorReason::Io(error),
+        })?;
+
+    parse_osu(path, &source)
+}
 
-    source.retain(|x| x != '\r');
-    let source_lines: Vec<&str> = source.split('\n').collect();
+/// Parse the contents of a .osu file. `file` is only used to describe errors
+pub fn parse_osu(file: &str, source: &str) -> Result<OsuBeatmap, OsuError> {
+    let mut beatmap = OsuBeatmap::default();
+    // Each time a new [Section] line is met, the lines that follow are parsed into that section
+    let mut current_section = Section::None;
+    let mut current_section_name = None;
+    // Old beatmap versions have no ApproachRate, and use the OverallDifficulty instead
+    let mut has_approach_rate = false;
+
+    for (line_index, line) in source.lines().enumerate() {
+        // Some editors leave trailing whitespace, and the BOM may precede the header
+        let line = line.trim_end().trim_start_matches('\u{feff}');
+
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
 
-    let mut sections: HashMap<String, OsuFileSection> = HashMap::new();
-    // Each time a new [Section] line is met, a new HashMap will be assigned to current_section_map
-    let mut current_section = &mut OsuFileSection::None;
+        let error = |reason| OsuError {
+            file: file.to_string(),
+            line: Some(line_index + 1),
+            section: current_section_name.clone(),
+            reason,
+        };
 
-    for line in source_lines.iter() {
-        if line.is_empty() {
+        if let Some(version) = line.strip_prefix("osu file format v") {
+            beatmap.format_version = parse_value(version).map_err(error)?;
             continue;
         }
 
         // If line starts with '[', this is a Section Title
         // Otherwise, this line contains data for the current Section
         if line.starts_with('[') {
-            // Create a new entry in the section map for that Section Title, with the type in the OsuFileSection Enum determined by the title name
-            current_section = sections.entry(line.to_string()).or_insert(match *line {
-                "[HitObjects]" => OsuFileSection::HitObjects(vec![]),
-                "[Events]" => OsuFileSection::Events(String::new()),
-                _ => OsuFileSection::KeyValueMap(HashMap::new()),
-            });
-        } else {
-            match current_section {
-                OsuFileSection::HitObjects(section_data) => {
-                    section_data.push(hitobject_processing(line))
-                }
-                OsuFileSection::Events(background) => {
-                    match event_processing(line) {
-                        Some(value) => *background = value,
-                        None => ()
+            current_section = match line {
+                "[General]" => Section::General,
+                "[Metadata]" => Section::Metadata,
+                "[Difficulty]" => Section::Difficulty,
+                "[Events]" => Section::Events,
+                "[TimingPoints]" => Section::TimingPoints,
+                "[Colours]" => Section::Colours,
+                "[HitObjects]" => Section::HitObjects,
+                _ => Section::Ignored,
+            };
+            current_section_name = Some(line.to_string());
+            continue;
+        }
+
+        let result = match current_section {
+            Section::General | Section::Metadata | Section::Difficulty | Section::Colours => {
+                let (key, value) = match json_like_key_value_get(line) {
+                    Some(pair) => pair,
+                    None => continue,
+                };
+
+                match current_section {
+                    Section::General => beatmap.general.set(key, value),
+                    Section::Metadata => beatmap.metadata.set(key, value),
+                    Section::Difficulty => {
+                        has_approach_rate |= key == "ApproachRate";
+                        beatmap.difficulty.set(key, value)
                     }
+                    _ => beatmap.colours.set(key, value),
                 }
-                OsuFileSection::KeyValueMap(section_map) => {
-                    match json_like_key_value_get(line) {
-                        Some(s) => section_map.insert(s.0, s.1),
-                        None => None,
-                    };
-                }
-                _ => {}
             }
-        }
+            Section::Events => event_processing(line, &mut beatmap.events),
+            Section::TimingPoints => timing_point_processing(line)
+                .map(|timing_point| beatmap.timing_points.0.push(timing_point)),
+            Section::HitObjects => hitobject_processing(line)
+                .map(|hit_object| beatmap.hit_objects.push(hit_object)),
+            Section::None | Section::Ignored => Ok(()),
+        };
+
+        result.map_err(error)?;
+    }
+
+    if !has_approach_rate {
+        beatmap.difficulty.approach_rate = beatmap.difficulty.overall_difficulty;
+    }
+    beatmap.timing_points.sort();
+
+    Ok(beatmap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A red line at 1000ms (120 BPM), a green line at 2000ms (2x, kiai) and a red line at 3000ms (150 BPM)
+    fn timing_points() -> TimingPoints {
+        let mut timing_points = TimingPoints(
+            [
+                "1000,500,4,2,0,100,1,0",
+                "2000,-50,4,2,0,80,0,1",
+                "3000,400,3,2,0,100,1,0",
+            ]
+            .iter()
+            .map(|line| timing_point_processing(line).unwrap())
+            .collect(),
+        );
+        timing_points.sort();
+        timing_points
     }
 
-    // DEBUG: Write all the parsed data into an output file
-    let output_path = path.replace(".osu", "_output.txt");
-    write(output_path, format!("{sections:#?}")).unwrap();
+    #[test]
+    fn inherited_points_keep_the_red_line_timing() {
+        let timing_points = timing_points();
 
-    sections
+        // The green line doesn't change the beat length or meter, only the effects
+        assert_eq!(timing_points.timing_point_at(2500.).unwrap().time, 1000.);
+        assert_eq!(timing_points.beat_length_at(2500.), Some(500.));
+        assert_eq!(timing_points.meter_at(2500.), Some(4));
+        assert_eq!(timing_points.slider_velocity_at(2500.), 2.);
+        assert!(timing_points.is_kiai_at(2500.));
+    }
+
+    #[test]
+    fn uninherited_points_reset_the_effects() {
+        let timing_points = timing_points();
+
+        assert_eq!(timing_points.beat_length_at(3000.), Some(400.));
+        assert_eq!(timing_points.meter_at(3500.), Some(3));
+        assert_eq!(timing_points.slider_velocity_at(3500.), 1.);
+        assert!(!timing_points.is_kiai_at(3500.));
+    }
+
+    #[test]
+    fn queries_before_the_first_point_use_the_first_red_line() {
+        let timing_points = timing_points();
+
+        assert_eq!(timing_points.beat_length_at(0.), Some(500.));
+        assert_eq!(timing_points.beat_at(0.), Some(-2.));
+        // There are no effects before the first point
+        assert!(timing_points.effect_point_at(0.).is_none());
+        assert_eq!(timing_points.slider_velocity_at(0.), 1.);
+        assert!(!timing_points.is_kiai_at(0.));
+    }
+
+    #[test]
+    fn no_timing_points() {
+        let timing_points = TimingPoints::default();
+
+        assert!(timing_points.timing_point_at(1000.).is_none());
+        assert_eq!(timing_points.beat_length_at(1000.), None);
+        assert!(!timing_points.is_kiai_at(1000.));
+    }
 }
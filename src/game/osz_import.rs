@@ -0,0 +1,264 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+use bevy::prelude::*;
+use bevy::window::FileDragAndDrop;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+use super::osu_reader::{self, OsuError};
+use super::BEATMAP_LIBRARY_PATH;
+
+//region Plugin Boilerplate
+pub struct OszImportPlugin;
+
+impl Plugin for OszImportPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ImportedBeatmapSets::default())
+            .add_startup_system(import_library_archives_system)
+            .add_system(import_dropped_archives_system);
+    }
+}
+//endregion
+
+//region Import Resources
+/// A beatmap set that was extracted into the library
+#[derive(Debug, Clone)]
+pub struct ImportedBeatmapSet {
+    /// The folder of the set, inside BEATMAP_LIBRARY_PATH
+    pub directory: PathBuf,
+    /// File names of the valid .osu difficulties, relative to `directory`
+    pub difficulties: Vec<String>,
+}
+
+/// Every set imported since the game started, in order.
+/// Anything that lists beatmaps should refresh when this changes
+#[derive(Default)]
+pub struct ImportedBeatmapSets(pub Vec<ImportedBeatmapSet>);
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io(io::Error),
+    Zip(ZipError),
+    /// None of the .osu files in the archive could be parsed (one error for each of them)
+    NoValidDifficulty(Vec<OsuError>),
+    /// The set folder already exists in the library
+    AlreadyImported(PathBuf),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Io(error) => write!(f, "{error}"),
+            ImportError::Zip(error) => write!(f, "invalid archive ({error})"),
+            ImportError::NoValidDifficulty(errors) if errors.is_empty() => {
+                write!(f, "the archive has no .osu files")
+            }
+            ImportError::NoValidDifficulty(errors) => {
+                write!(f, "no valid difficulty, first error: {}", errors[0])
+            }
+            ImportError::AlreadyImported(directory) => {
+                write!(f, "{} already exists", directory.display())
+            }
+        }
+    }
+}
+
+impl Error for ImportError {}
+
+impl From<io::Error> for ImportError {
+    fn from(error: io::Error) -> Self {
+        ImportError::Io(error)
+    }
+}
+
+impl From<ZipError> for ImportError {
+    fn from(error: ZipError) -> Self {
+        ImportError::Zip(error)
+    }
+}
+//endregion
+
+/// Extract the difficulties of the .osz archive at `archive_path`, along with the audio and
+/// backgrounds they use, into a new folder of `library_path`.
+/// Difficulties that don't parse are left out. Storyboards, videos and custom hit sounds are not extracted
+pub fn import_osz(archive_path: &Path, library_path: &Path) -> Result<ImportedBeatmapSet, ImportError> {
+    let mut archive = ZipArchive::new(File::open(archive_path)?)?;
+
+    // Beatmaps reference files case-insensitively (osu! started on Windows)
+    let mut entries: HashMap<String, usize> = HashMap::new();
+    for index in 0..archive.len() {
+        let entry = archive.by_index(index)?;
+        if let Some(name) = entry.enclosed_name() {
+            let name = name.to_string_lossy().replace('\\', "/").to_lowercase();
+            entries.insert(name, index);
+        }
+    }
+
+    //region Validate every difficulty
+    let mut difficulties = vec![];
+    let mut errors = vec![];
+    let mut referenced_files = HashSet::new();
+
+    let mut osu_entries: Vec<(&String, &usize)> =
+        entries.iter().filter(|(name, _)| name.ends_with(".osu")).collect();
+    osu_entries.sort();
+
+    for (_, index) in osu_entries {
+        let mut entry = archive.by_index(*index)?;
+        let name = entry.name().replace('\\', "/");
+
+        let mut source = String::new();
+        entry.read_to_string(&mut source)?;
+
+        match osu_reader::parse_osu(&name, &source) {
+            Ok(beatmap) => {
+                // Kept as the beatmap spells them, since that's the path the game loads
+                referenced_files.insert(beatmap.general.audio_filename.replace('\\', "/"));
+                if let Some(background) = beatmap.events.background {
+                    referenced_files.insert(background.replace('\\', "/"));
+                }
+                difficulties.push((*index, name));
+            }
+            Err(error) => {
+                warn!("Skipping difficulty: {error}");
+                errors.push(error);
+            }
+        }
+    }
+
+    if difficulties.is_empty() {
+        return Err(ImportError::NoValidDifficulty(errors));
+    }
+    //endregion
+
+    //region Extract into the library
+    let set_name = archive_path
+        .file_stem()
+        .map_or("Unknown".to_string(), |stem| sanitize_file_name(&stem.to_string_lossy()));
+    let directory = library_path.join(&set_name);
+
+    if directory.exists() {
+        return Err(ImportError::AlreadyImported(directory));
+    }
+
+    // Extract next to the set folder first, so a failed import doesn't leave half a set behind
+    let staging = library_path.join(format!("{set_name}.importing"));
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+
+    let extracted = extract_entries(&mut archive, &entries, &difficulties, &referenced_files, &staging)
+        .and_then(|()| fs::rename(&staging, &directory).map_err(ImportError::from));
+    if let Err(error) = extracted {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(error);
+    }
+    //endregion
+
+    Ok(ImportedBeatmapSet {
+        directory,
+        difficulties: difficulties.into_iter().map(|(_, name)| name).collect(),
+    })
+}
+
+/// Write the difficulties under their names in the archive, and the files they reference under the
+/// exact names the beatmaps use, since the archive's case may differ and not every file system ignores it
+fn extract_entries(
+    archive: &mut ZipArchive<File>,
+    entries: &HashMap<String, usize>,
+    difficulties: &[(usize, String)],
+    referenced_files: &HashSet<String>,
+    directory: &Path,
+) -> Result<(), ImportError> {
+    let difficulty_entries = difficulties
+        .iter()
+        .map(|(index, name)| (*index, PathBuf::from(name)));
+    let referenced_entries = referenced_files.iter().filter_map(|name| {
+        let index = entries.get(&name.to_lowercase())?;
+        Some((*index, PathBuf::from(name)))
+    });
+
+    for (index, relative_path) in difficulty_entries.chain(referenced_entries) {
+        // Names come from the archive and the beatmaps, they must not escape the set folder
+        if !relative_path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            continue;
+        }
+
+        let output_path = directory.join(relative_path);
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        io::copy(&mut archive.by_index(index)?, &mut File::create(output_path)?)?;
+    }
+
+    Ok(())
+}
+
+/// Remove the characters that aren't allowed in folder names on any platform
+fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .filter(|character| !matches!(character, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*'))
+        .collect::<String>()
+        .trim_end_matches(['.', ' '])
+        .to_string()
+}
+
+fn import_and_register(archive_path: &Path, imported: &mut ImportedBeatmapSets) {
+    match import_osz(archive_path, Path::new(BEATMAP_LIBRARY_PATH)) {
+        Ok(set) => {
+            info!(
+                "Imported {} ({} difficulties)",
+                set.directory.display(),
+                set.difficulties.len()
+            );
+            imported.0.push(set);
+        }
+        Err(error) => error!("Could not import {}: {error}", archive_path.display()),
+    }
+}
+
+/// Import the .osz archives that were copied into the library folder, like osu! does with its Songs folder.
+/// An archive whose set folder already exists has been imported before, and is skipped
+fn import_library_archives_system(mut imported: ResMut<ImportedBeatmapSets>) {
+    let archives = match fs::read_dir(BEATMAP_LIBRARY_PATH) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "osz")),
+        Err(_) => return, // No library yet
+    };
+
+    for archive_path in archives {
+        let set_name = archive_path
+            .file_stem()
+            .map(|stem| sanitize_file_name(&stem.to_string_lossy()));
+        if let Some(set_name) = set_name {
+            if Path::new(BEATMAP_LIBRARY_PATH).join(set_name).exists() {
+                continue;
+            }
+        }
+
+        import_and_register(&archive_path, &mut imported);
+    }
+}
+
+/// Import the .osz archives dropped onto the window
+fn import_dropped_archives_system(
+    mut drag_and_drop: EventReader<FileDragAndDrop>,
+    mut imported: ResMut<ImportedBeatmapSets>,
+) {
+    for event in drag_and_drop.iter() {
+        if let FileDragAndDrop::DroppedFile { path_buf, .. } = event {
+            if path_buf.extension().is_some_and(|extension| extension == "osz") {
+                import_and_register(path_buf, &mut imported);
+            }
+        }
+    }
+}
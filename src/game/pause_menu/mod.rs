@@ -1,23 +1,136 @@
+use bevy::prelude::*;
+use bevy_kira_audio::{AudioChannel, AudioControl};
 
+use crate::GameStates;
 
-fn spawn_pause_menu_system(
+use super::beatmap_plugin::MusicChannel;
+use super::common_systems::RestartEvent;
+use super::controls::ActiveGamepad;
+use super::key_bindings::{Action, GamepadBindings, KeyBindings};
+use super::ui_plugin::{button, button_system, button_text, menu_background};
+use super::{despawn_with, FontHandles, PAUSE_RESUME_COUNTDOWN};
 
+//region Plugin Boilerplate
+/// Pausing pushes GameStates::Paused on top of GameStates::Game. Every gameplay system only runs
+/// while Game is the current state, so physics, animations, the song clock and spawning all
+/// freeze until it is popped
+pub struct PauseMenuPlugin;
+
+impl Plugin for PauseMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ResumeCountdown::default())
+            .add_system_set(
+                SystemSet::on_update(GameStates::Game).with_system(pause_system),
+            )
+            .add_system_set(
+                SystemSet::on_enter(GameStates::Paused).with_system(pause_menu_setup_system),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameStates::Paused)
+                    .with_system(button_system)
+                    .with_system(pause_menu_button_system)
+                    .with_system(pause_menu_input_system)
+                    .with_system(resume_countdown_system),
+            )
+            // The controls menu is pushed on top of the pause menu, which hides while it is open
+            .add_system_set(
+                SystemSet::on_pause(GameStates::Paused).with_system(despawn_with::<PauseMenuEntity>),
+            )
+            .add_system_set(
+                SystemSet::on_resume(GameStates::Paused).with_system(pause_menu_setup_system),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameStates::Paused)
+                    .with_system(despawn_with::<PauseMenuEntity>)
+                    .with_system(pause_menu_exit_system),
+            );
+    }
+}
+//endregion
+
+//region Pause Menu Resources and Components
+/// Counts down before the game resumes, so the player has time to get ready
+#[derive(Default)]
+struct ResumeCountdown(Option<Timer>);
+
+/// Everything spawned by the pause menu, despawned when leaving it
+#[derive(Component)]
+struct PauseMenuEntity;
+
+#[derive(Component)]
+enum PauseMenuButton {
+    Resume,
+    Retry,
+    Settings,
+    Quit,
+}
+
+#[derive(Component)]
+struct CountdownText;
+//endregion
+
+/// Whether the Pause action was just pressed, on the keyboard or the gamepad
+fn pause_just_pressed(
+    keyboard: &Input<KeyCode>,
+    key_bindings: &KeyBindings,
+    gamepad_buttons: &Input<GamepadButton>,
+    gamepad_bindings: &GamepadBindings,
+    active_gamepad: &ActiveGamepad,
+) -> bool {
+    key_bindings.just_pressed(keyboard, Action::Pause)
+        || active_gamepad.0.is_some_and(|gamepad| {
+            gamepad_bindings.just_pressed(gamepad_buttons, gamepad, Action::Pause)
+        })
+}
+
+fn pause_system(
+    keyboard: Res<Input<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_bindings: Res<GamepadBindings>,
+    active_gamepad: Res<ActiveGamepad>,
+    music_channel: Res<AudioChannel<MusicChannel>>,
+    mut game_state: ResMut<State<GameStates>>,
+) {
+    if pause_just_pressed(&keyboard, &key_bindings, &gamepad_buttons, &gamepad_bindings, &active_gamepad) {
+        // The song clock stops with the state, the music has to be paused with it
+        music_channel.pause();
+        game_state.overwrite_push(GameStates::Paused).unwrap();
+    }
+}
+
+fn pause_menu_setup_system(
+    mut commands: Commands,
+    fonts: Res<FontHandles>,
+    mut windows: ResMut<Windows>,
+    mut keyboard: ResMut<Input<KeyCode>>,
+    mut gamepad_buttons: ResMut<Input<GamepadButton>>,
+    mut countdown: ResMut<ResumeCountdown>,
 ) {
-    // Pause Menu
+    windows.get_primary_mut().unwrap().set_cursor_visibility(true);
+
+    // The key that opened (or went back to) the menu would otherwise be read again this frame
+    keyboard.clear();
+    gamepad_buttons.clear();
+
+    countdown.0 = None;
+    spawn_pause_menu(&mut commands, &fonts.rubik_regular);
+}
+
+fn spawn_pause_menu(commands: &mut Commands, font: &Handle<Font>) {
     commands
         .spawn_bundle(NodeBundle {
             style: Style {
-                padding: UiRect::all(Val::Px(20.)),
-                // region Align to Top-Right
-                align_self: AlignSelf::Center,
-                flex_grow: 0.75,
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
                 justify_content: JustifyContent::Center,
-                // endregion
+                align_items: AlignItems::Center,
                 ..Default::default()
             },
-            color: UiColor(Color::rgba(0., 0., 0., 0.0)),
+            // Darken the frozen game behind the menu
+            color: UiColor(Color::rgba(0., 0., 0., 0.5)),
             ..Default::default()
         })
+        .insert(PauseMenuEntity)
         .with_children(|parent| {
             parent
                 .spawn_bundle(menu_background(Color::BLACK))
@@ -28,41 +141,158 @@ fn spawn_pause_menu_system(
                         .spawn_bundle(NodeBundle {
                             style: Style {
                                 padding: UiRect::all(Val::Px(20.)),
-                                // region Align to Top-Right
                                 align_self: AlignSelf::Center,
                                 flex_grow: 1.0,
                                 justify_content: JustifyContent::Center,
-                                // endregion
                                 ..Default::default()
                             },
                             color: UiColor(Color::rgba(0., 0., 0., 0.0)),
                             ..Default::default()
                         })
                         .with_children(|parent| {
-                            parent.spawn_bundle(button_text(
-                                Color::WHITE,
-                                &font,
-                                "Gameplay Options",
-                            ));
+                            parent.spawn_bundle(button_text(Color::WHITE, font, "Paused"));
                         });
 
-                    // Dash Stop Button
-                    parent
-                        .spawn_bundle(button(Color::DARK_GRAY))
-                        .with_children(|parent| {
-                            parent.spawn_bundle(button_text(Color::WHITE, &font, ""));
-                        })
-                        .insert(ToggleButton("Dash Stop".to_string(), false))
-                        .insert(SettingsButton::DashStop);
-
-                    // Snap on Cut Button
-                    parent
-                        .spawn_bundle(button(Color::DARK_GRAY))
-                        .with_children(|parent| {
-                            parent.spawn_bundle(button_text(Color::WHITE, &font, ""));
-                        })
-                        .insert(ToggleButton("Snap on Cut".to_string(), true))
-                        .insert(SettingsButton::SnapOnCut);
+                    for (label, pause_button) in [
+                        ("Resume", PauseMenuButton::Resume),
+                        ("Retry", PauseMenuButton::Retry),
+                        ("Settings", PauseMenuButton::Settings),
+                        ("Quit to Song Select", PauseMenuButton::Quit),
+                    ] {
+                        parent
+                            .spawn_bundle(button(Color::DARK_GRAY))
+                            .with_children(|parent| {
+                                parent.spawn_bundle(button_text(Color::WHITE, font, label));
+                            })
+                            .insert(pause_button);
+                    }
                 });
         });
-}
\ No newline at end of file
+}
+
+fn spawn_countdown_text(commands: &mut Commands, font: &Handle<Font>) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: UiColor(Color::rgba(0., 0., 0., 0.0)),
+            ..Default::default()
+        })
+        .insert(PauseMenuEntity)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 120.,
+                        color: Color::WHITE,
+                    },
+                ))
+                .insert(CountdownText);
+        });
+}
+
+fn pause_menu_exit_system(mut windows: ResMut<Windows>, mut countdown: ResMut<ResumeCountdown>) {
+    windows.get_primary_mut().unwrap().set_cursor_visibility(false);
+    countdown.0 = None;
+}
+
+fn pause_menu_button_system(
+    mut commands: Commands,
+    buttons: Query<(&Interaction, &PauseMenuButton), Changed<Interaction>>,
+    menu: Query<Entity, With<PauseMenuEntity>>,
+    fonts: Res<FontHandles>,
+    music_channel: Res<AudioChannel<MusicChannel>>,
+    mut countdown: ResMut<ResumeCountdown>,
+    mut restart_events: EventWriter<RestartEvent>,
+    mut game_state: ResMut<State<GameStates>>,
+) {
+    for (interaction, pause_button) in buttons.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+
+        match pause_button {
+            PauseMenuButton::Resume => {
+                menu.for_each(|entity| commands.entity(entity).despawn_recursive());
+                start_countdown(&mut commands, &fonts, &mut countdown);
+            }
+            PauseMenuButton::Retry => {
+                // Unpause the channel so the restarted song doesn't start paused
+                music_channel.resume();
+                music_channel.stop();
+                restart_events.send(RestartEvent::All);
+                game_state.overwrite_pop().unwrap();
+            }
+            PauseMenuButton::Settings => game_state.overwrite_push(GameStates::Controls).unwrap(),
+            PauseMenuButton::Quit => {
+                music_channel.resume();
+                music_channel.stop();
+                // Leaves both the pause menu and the game
+                game_state.overwrite_replace(GameStates::SongSelect).unwrap();
+            }
+        }
+        return;
+    }
+}
+
+fn start_countdown(commands: &mut Commands, fonts: &FontHandles, countdown: &mut ResumeCountdown) {
+    countdown.0 = Some(Timer::from_seconds(PAUSE_RESUME_COUNTDOWN, false));
+    spawn_countdown_text(commands, &fonts.rubik_regular);
+}
+
+fn pause_menu_input_system(
+    mut commands: Commands,
+    keyboard: Res<Input<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_bindings: Res<GamepadBindings>,
+    active_gamepad: Res<ActiveGamepad>,
+    menu: Query<Entity, With<PauseMenuEntity>>,
+    fonts: Res<FontHandles>,
+    mut countdown: ResMut<ResumeCountdown>,
+) {
+    if !pause_just_pressed(&keyboard, &key_bindings, &gamepad_buttons, &gamepad_bindings, &active_gamepad) {
+        return;
+    }
+
+    menu.for_each(|entity| commands.entity(entity).despawn_recursive());
+    if countdown.0.is_some() {
+        // Pausing again during the countdown goes back to the menu
+        countdown.0 = None;
+        spawn_pause_menu(&mut commands, &fonts.rubik_regular);
+    } else {
+        start_countdown(&mut commands, &fonts, &mut countdown);
+    }
+}
+
+fn resume_countdown_system(
+    time: Res<Time>,
+    music_channel: Res<AudioChannel<MusicChannel>>,
+    mut countdown: ResMut<ResumeCountdown>,
+    mut countdown_text: Query<&mut Text, With<CountdownText>>,
+    mut game_state: ResMut<State<GameStates>>,
+) {
+    let timer = match countdown.0.as_mut() {
+        Some(timer) => timer,
+        None => return,
+    };
+    timer.tick(time.delta());
+
+    if timer.finished() {
+        // The song clock was frozen at the same position as the music, so both carry on in sync
+        music_channel.resume();
+        game_state.overwrite_pop().unwrap();
+        return;
+    }
+
+    let seconds_left = (timer.duration() - timer.elapsed()).as_secs_f32().ceil();
+    for mut text in countdown_text.iter_mut() {
+        text.sections[0].value = format!("{seconds_left}");
+    }
+}
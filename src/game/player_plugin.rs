@@ -1,12 +1,12 @@
-use crate::game::common_components::{IsOnWall, TimeAnimation, Velocity, Walls};
-use crate::game::common_systems::RestartEvent;
+use crate::game::common_components::{GameEntity, IsOnWall, TimeAnimation, Velocity, Walls};
+use crate::game::common_systems::{PhysicsStage, RestartEvent};
 use crate::game::controls::{Dash, Movement};
 use crate::game::fruit_plugin::CutAffects;
 use crate::game::{
     TexturesHandles, DASH_DURATION, DASH_SPEED, FRUITS_SIZE, JUMP_OFF_WALL_SPEED_ATTRITION,
     MAX_PLAYER_DASHES_MIDAIR, MAX_PLAYER_JUMPS_MIDAIR, PLAYER_FAST_FALLING_SPEED, PLAYER_GRAVITY,
     PLAYER_GRAVITY_ON_WALL, PLAYER_HORIZONTAL_JUMP_WALL, PLAYER_JUMP, PLAYER_SCALE, PLAYER_SIZE,
-    PLAYER_SPEED, PLAYER_VERTICAL_JUMP_WALL,
+    PHYSICS_STEP_SCALE, PLAYER_SPEED, PLAYER_VERTICAL_JUMP_WALL,
 };
 
 use crate::GameStates;
@@ -14,6 +14,7 @@ use bevy::prelude::*;
 use bevy::sprite::collide_aabb::collide;
 
 use super::fruit_plugin::Fruit;
+use super::mods::Mods;
 use super::GameSettings;
 
 //region Plugin boilerplate
@@ -25,16 +26,24 @@ impl Plugin for PlayerPlugin {
             SystemSet::on_enter(GameStates::Game) // Post startup
                 .with_system(spawn_player_system),
         )
+        .add_system_set_to_stage(
+            PhysicsStage, // Once per fixed step, see CommonSystems for the order
+            SystemSet::new()
+                .with_system(can_dash_system.label("dash").after("input"))
+                .with_system(dash_system.label("dash").after(can_dash_system))
+                .with_system(player_movement_wall_system.label("player_movement").after("dash"))
+                .with_system(
+                    player_movement_air_system
+                        .label("player_movement")
+                        .after(player_movement_wall_system),
+                )
+                .with_system(player_corners_system.label("corners").after("move"))
+                .with_system(fruit_collision_system.label("collisions").after("corners"))
+                .with_system(player_bottom_system.after("collisions")),
+        )
         .add_system_set(
             SystemSet::on_update(GameStates::Game) // Normal systems
-                .with_system(can_dash_system)
-                .with_system(dash_system)
                 .with_system(dash_aura_system)
-                .with_system(fruit_collision_system)
-                .with_system(player_bottom_system)
-                .with_system(player_corners_system)
-                .with_system(player_movement_air_system)
-                .with_system(player_movement_wall_system)
                 .with_system(player_flip_system),
         );
     }
@@ -94,6 +103,7 @@ fn spawn_player_system(mut commands: Commands, textures: Res<TexturesHandles>) {
             ..Default::default()
         })
         .insert(Player)
+        .insert(GameEntity)
         .insert(Velocity::default())
         .insert(IsOnWall(None))
         .insert(JumpOffWallSpeed::default())
@@ -149,7 +159,6 @@ fn player_corners_system(
 fn player_movement_air_system(
     mut query: Query<(&mut Velocity, &mut JumpOffWallSpeed, &mut IsOnWall), With<Player>>,
     mut movement: ResMut<Movement>,
-    time: Res<Time>,
     dash: Res<Dash>,
 ) {
     for (mut velocity, mut jows, mut wall) in query.iter_mut() {
@@ -185,7 +194,7 @@ fn player_movement_air_system(
             velocity.y = PLAYER_FAST_FALLING_SPEED;
         } else {
             // Apply Gravity
-            velocity.y -= PLAYER_GRAVITY * 60. * time.delta_seconds();
+            velocity.y -= PLAYER_GRAVITY * PHYSICS_STEP_SCALE;
         }
 
         //region Apply JumpOffWallSpeed
@@ -283,7 +292,6 @@ fn can_dash_system(
 fn dash_system(
     mut query: Query<(&mut Velocity, &mut JumpOffWallSpeed), With<Player>>,
     mut dash: ResMut<Dash>,
-    time: Res<Time>,
     movement: Res<Movement>,
 ) {
     if !dash.is_dashing {
@@ -311,7 +319,7 @@ fn dash_system(
 
         jows.reset();
 
-        dash.apply_time(&time);
+        dash.apply_time();
     }
 }
 
@@ -332,6 +340,7 @@ fn fruit_collision_system(
     mut movement: ResMut<Movement>,
     mut dash: ResMut<Dash>,
     game_settings: ResMut<GameSettings>,
+    mods: Res<Mods>,
 ) {
     // The fruits are only cut when the player is dashing
     if !dash.is_dashing {
@@ -348,7 +357,7 @@ fn fruit_collision_system(
                 player_tf.translation,
                 PLAYER_SIZE,
                 fruits_tf.translation,
-                FRUITS_SIZE,
+                FRUITS_SIZE * mods.fruit_scale(),
             );
 
             if let Some(_) = collision {
@@ -381,14 +390,16 @@ fn dash_aura_system(mut query: Query<&mut Visibility, With<DashAura>>, dash: Res
 fn player_bottom_system(
     mut query: Query<&IsOnWall, With<Player>>,
     mut restart_events: EventWriter<RestartEvent>,
-    game_settings: Res<GameSettings>,
-    
+    mut fell: Local<bool>,
 ) {
     for is_on_wall in query.iter_mut() {
-        if matches!(is_on_wall.0, Some(Walls::Floor)) {
-            // Request game to be restarted
-            restart_events.send(if game_settings.no_death_penalty { RestartEvent::OnlyPlayer } else { RestartEvent::All });
+        let on_floor = matches!(is_on_wall.0, Some(Walls::Floor));
+        // The player is only put back next frame, so later steps of this frame would see the same fall
+        if on_floor && !*fell {
+            // Put the player back on the stage. Falling costs HP, the HP bar decides whether the run fails
+            restart_events.send(RestartEvent::OnlyPlayer);
         }
+        *fell = on_floor;
     }
 }
 
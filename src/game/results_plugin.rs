@@ -0,0 +1,334 @@
+use bevy::prelude::*;
+use bevy_kira_audio::{AudioChannel, AudioControl};
+
+use crate::GameStates;
+
+use super::beatmap_library::SelectedBeatmap;
+use super::beatmap_plugin::{Beatmap, BeatmapPlayback, MusicChannel, SongClock};
+use super::fruit_plugin::Fruit;
+use super::high_scores::{BeatmapKey, HighScores, RecordedScore};
+use super::key_bindings::{Action, KeyBindings};
+use super::mods::Mods;
+use super::scoring::{HitWindows, Judgement, ScoreState};
+use super::ui_plugin::{button, button_system, button_text};
+use super::{despawn_with, FontHandles, HIGH_SCORES_SHOWN, HIT_ERROR_HISTOGRAM_BINS, RESULTS_DELAY};
+
+const HISTOGRAM_WIDTH: f32 = 500.;
+const HISTOGRAM_HEIGHT: f32 = 120.;
+
+//region Plugin Boilerplate
+pub struct ResultsPlugin;
+
+impl Plugin for ResultsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_update(GameStates::Game).with_system(beatmap_end_system),
+        )
+        .add_system_set(
+            SystemSet::on_enter(GameStates::Results)
+                // The high score list includes the score of this run
+                .with_system(results_setup_system.after("record_high_score")),
+        )
+        .add_system_set(
+            SystemSet::on_update(GameStates::Results)
+                .with_system(button_system)
+                .with_system(results_button_system)
+                .with_system(results_keyboard_system),
+        )
+        .add_system_set(
+            SystemSet::on_exit(GameStates::Results)
+                .with_system(results_exit_system)
+                .with_system(despawn_with::<ResultsEntity>),
+        );
+    }
+}
+//endregion
+
+//region Results Components
+/// Everything spawned by the results screen, despawned when leaving it
+#[derive(Component)]
+struct ResultsEntity;
+
+#[derive(Component)]
+enum ResultsButton {
+    Retry,
+    SongSelect,
+}
+//endregion
+
+/// Go to the results once every HitObject has been spawned and every fruit is gone
+fn beatmap_end_system(
+    beatmap: Res<Beatmap>,
+    beatmap_playback: Res<BeatmapPlayback>,
+    song_clock: Res<SongClock>,
+    fruits: Query<(), With<Fruit>>,
+    mut game_state: ResMut<State<GameStates>>,
+) {
+    let hit_objects = &beatmap.0.hit_objects;
+    if beatmap_playback.current_hit_object_id < hit_objects.len() || !fruits.is_empty() {
+        return;
+    }
+
+    let end_time = hit_objects
+        .last()
+        .map_or(0., |last| beatmap.0.end_time(last) as f64);
+
+    if song_clock.position > end_time + (RESULTS_DELAY * 1000.) as f64 {
+        game_state.overwrite_set(GameStates::Results).unwrap();
+    }
+}
+
+fn results_setup_system(
+    mut commands: Commands,
+    fonts: Res<FontHandles>,
+    mut windows: ResMut<Windows>,
+    score: Res<ScoreState>,
+    beatmap: Res<Beatmap>,
+    high_scores: Res<HighScores>,
+    recorded_score: Res<RecordedScore>,
+    selected_beatmap: Res<SelectedBeatmap>,
+    mods: Res<Mods>,
+    key_bindings: Res<KeyBindings>,
+) {
+    // The buttons are clicked with the mouse
+    windows.get_primary_mut().unwrap().set_cursor_visibility(true);
+
+    let font = fonts.rubik_regular.clone();
+    let text_style = |font_size: f32, color: Color| TextStyle {
+        font: font.clone(),
+        font_size,
+        color,
+    };
+
+    let metadata = &beatmap.0.metadata;
+    let grade = score.grade();
+    let judgements = &score.judgements;
+    let hit_windows = HitWindows::from_overall_difficulty(
+        mods.overall_difficulty(beatmap.0.difficulty.overall_difficulty),
+    );
+    let top_scores = high_scores.top(&BeatmapKey::from(&selected_beatmap.0), HIGH_SCORES_SHOWN);
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+            },
+            color: UiColor(Color::rgba(0., 0., 0., 0.0)),
+            ..Default::default()
+        })
+        .insert(ResultsEntity)
+        .with_children(|parent| {
+            // Beatmap
+            let mut beatmap_line =
+                format!("{} - {} [{}]", metadata.artist, metadata.title, metadata.version);
+            if !mods.is_empty() {
+                beatmap_line += &format!(" +{}", *mods);
+            }
+            parent.spawn_bundle(TextBundle::from_section(
+                beatmap_line,
+                text_style(24., Color::WHITE),
+            ));
+
+            // Grade
+            parent.spawn_bundle(
+                TextBundle::from_section(grade.to_string(), text_style(96., grade.color()))
+                    .with_style(Style {
+                        margin: UiRect::all(Val::Px(10.)),
+                        ..Default::default()
+                    }),
+            );
+
+            // Score
+            parent.spawn_bundle(TextBundle::from_section(
+                format!(
+                    "Score: {}\nAccuracy: {:.2}%\nMax Combo: {}",
+                    score.total_score,
+                    score.accuracy(),
+                    score.max_combo
+                ),
+                text_style(28., Color::WHITE),
+            ));
+
+            parent.spawn_bundle(
+                TextBundle::from_section(
+                    format!(
+                        "Perfect: {}   Great: {}   Good: {}   Miss: {}",
+                        judgements.perfect, judgements.great, judgements.good, judgements.miss
+                    ),
+                    text_style(20., Color::WHITE),
+                )
+                .with_style(Style {
+                    margin: UiRect::all(Val::Px(10.)),
+                    ..Default::default()
+                }),
+            );
+
+            // Hit error histogram
+            spawn_histogram(parent, &score.hit_errors, &hit_windows);
+
+            parent.spawn_bundle(TextBundle::from_section(
+                match score.mean_hit_error() {
+                    Some(mean) => format!("Early  <  mean error: {mean:+.1}ms  >  Late"),
+                    None => "No fruits were cut".to_string(),
+                },
+                text_style(16., Color::GRAY),
+            ));
+
+            // High scores, with this run highlighted
+            parent.spawn_bundle(
+                TextBundle::from_section(
+                    match recorded_score.rank {
+                        Some(0) => "New personal best!".to_string(),
+                        Some(rank) => format!("Local rank #{}", rank + 1),
+                        None => "Not a local high score".to_string(),
+                    },
+                    text_style(20., Color::GOLD),
+                )
+                .with_style(Style {
+                    margin: UiRect::new(Val::Px(0.), Val::Px(0.), Val::Px(15.), Val::Px(5.)),
+                    ..Default::default()
+                }),
+            );
+            for (rank, high_score) in top_scores.iter().enumerate() {
+                let color = if recorded_score.rank == Some(rank) {
+                    Color::WHITE
+                } else {
+                    Color::GRAY
+                };
+                parent.spawn_bundle(TextBundle::from_section(
+                    format!("#{}  {}", rank + 1, high_score.summary()),
+                    text_style(16., color),
+                ));
+            }
+
+            // Buttons
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Auto, Val::Auto),
+                        margin: UiRect::all(Val::Px(20.)),
+                        ..Default::default()
+                    },
+                    color: UiColor(Color::rgba(0., 0., 0., 0.0)),
+                    ..Default::default()
+                })
+                .with_children(|parent| {
+                    let retry_label = format!("Retry ({})", key_bindings.key_names(Action::Retry));
+                    for (label, results_button) in [
+                        (retry_label.as_str(), ResultsButton::Retry),
+                        ("Song Select (Esc)", ResultsButton::SongSelect),
+                    ] {
+                        parent
+                            .spawn_bundle(ButtonBundle {
+                                style: Style {
+                                    size: Size::new(Val::Px(200.), Val::Px(40.)),
+                                    margin: UiRect::all(Val::Px(5.)),
+                                    justify_content: JustifyContent::Center,
+                                    align_items: AlignItems::Center,
+                                    ..Default::default()
+                                },
+                                ..button(Color::DARK_GRAY)
+                            })
+                            .with_children(|parent| {
+                                parent.spawn_bundle(button_text(Color::WHITE, &font, label));
+                            })
+                            .insert(results_button);
+                    }
+                });
+        });
+}
+
+/// Bars counting the cuts by timing error, from the earliest to the latest Good.
+/// Each bar is colored by the judgement its errors would get
+fn spawn_histogram(parent: &mut ChildBuilder, hit_errors: &[f64], hit_windows: &HitWindows) {
+    let bin_width = 2. * hit_windows.good / HIT_ERROR_HISTOGRAM_BINS as f64;
+
+    let mut bins = [0usize; HIT_ERROR_HISTOGRAM_BINS];
+    for error in hit_errors {
+        let bin = ((error + hit_windows.good) / bin_width).floor() as isize;
+        bins[bin.clamp(0, HIT_ERROR_HISTOGRAM_BINS as isize - 1) as usize] += 1;
+    }
+    let highest = bins.iter().copied().max().unwrap_or(0).max(1);
+
+    parent
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Px(HISTOGRAM_WIDTH), Val::Px(HISTOGRAM_HEIGHT)),
+                // Bars grow up from the bottom
+                align_items: AlignItems::FlexStart,
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+            },
+            color: UiColor(Color::rgba(0., 0., 0., 0.3)),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            for (bin, count) in bins.iter().enumerate() {
+                let center = -hit_windows.good + (bin as f64 + 0.5) * bin_width;
+                let color = match hit_windows.judge(center) {
+                    Judgement::Perfect => Color::rgb(0.4, 0.8, 1.),
+                    Judgement::Great => Color::rgb(0.4, 0.9, 0.4),
+                    _ => Color::rgb(0.9, 0.7, 0.3),
+                };
+
+                parent.spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(
+                            Val::Px(HISTOGRAM_WIDTH / HIT_ERROR_HISTOGRAM_BINS as f32 - 2.),
+                            Val::Percent(*count as f32 / highest as f32 * 100.),
+                        ),
+                        margin: UiRect::horizontal(Val::Px(1.)),
+                        ..Default::default()
+                    },
+                    color: UiColor(color),
+                    ..Default::default()
+                });
+            }
+        });
+}
+
+fn results_button_system(
+    buttons: Query<(&Interaction, &ResultsButton), Changed<Interaction>>,
+    mut game_state: ResMut<State<GameStates>>,
+) {
+    for (interaction, results_button) in buttons.iter() {
+        if *interaction == Interaction::Clicked {
+            leave_results(results_button, &mut game_state);
+            return;
+        }
+    }
+}
+
+fn results_keyboard_system(
+    keyboard: Res<Input<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut game_state: ResMut<State<GameStates>>,
+) {
+    if key_bindings.just_pressed(&keyboard, Action::Retry) {
+        leave_results(&ResultsButton::Retry, &mut game_state);
+    } else if keyboard.just_pressed(KeyCode::Escape) {
+        leave_results(&ResultsButton::SongSelect, &mut game_state);
+    }
+}
+
+fn leave_results(results_button: &ResultsButton, game_state: &mut State<GameStates>) {
+    match results_button {
+        // SelectedBeatmap is still the beatmap that was just played
+        ResultsButton::Retry => game_state.overwrite_set(GameStates::Loading).unwrap(),
+        ResultsButton::SongSelect => game_state.overwrite_set(GameStates::SongSelect).unwrap(),
+    }
+}
+
+fn results_exit_system(
+    mut windows: ResMut<Windows>,
+    music_channel: Res<AudioChannel<MusicChannel>>,
+) {
+    windows.get_primary_mut().unwrap().set_cursor_visibility(false);
+
+    // The end of the song keeps playing on the results screen
+    music_channel.stop();
+}
@@ -0,0 +1,258 @@
+use std::fmt;
+
+use bevy::prelude::Color;
+
+use super::COMBO_SCORE_DIVISOR;
+
+//region Judgements
+/// How close to its hit time a fruit was cut
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Judgement {
+    Perfect,
+    Great,
+    Good,
+    Miss,
+}
+
+impl Judgement {
+    /// Base score of the judgement, like osu!'s 300/100/50
+    pub fn score_value(&self) -> u64 {
+        match self {
+            Judgement::Perfect => 300,
+            Judgement::Great => 100,
+            Judgement::Good => 50,
+            Judgement::Miss => 0,
+        }
+    }
+
+    pub fn breaks_combo(&self) -> bool {
+        matches!(self, Judgement::Miss)
+    }
+}
+
+/// Sent for every judgement, for the systems that react to them (such as the HP bar)
+pub struct JudgementEvent(pub Judgement);
+
+/// The largest timing errors (in milliseconds, either way) that still give each judgement
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HitWindows {
+    pub perfect: f64,
+    pub great: f64,
+    pub good: f64,
+}
+
+impl HitWindows {
+    /// The same windows osu! uses, which shrink as the OverallDifficulty goes up
+    pub fn from_overall_difficulty(overall_difficulty: f32) -> Self {
+        let od = overall_difficulty.clamp(0., 10.) as f64;
+
+        HitWindows {
+            perfect: 80. - 6. * od,
+            great: 140. - 8. * od,
+            good: 200. - 10. * od,
+        }
+    }
+
+    /// Judge a cut that happened `error` milliseconds after the hit time (negative if early)
+    pub fn judge(&self, error: f64) -> Judgement {
+        let error = error.abs();
+
+        if error <= self.perfect {
+            Judgement::Perfect
+        } else if error <= self.great {
+            Judgement::Great
+        } else if error <= self.good {
+            Judgement::Good
+        } else {
+            Judgement::Miss
+        }
+    }
+}
+//endregion
+
+//region Counting
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JudgementCounts {
+    pub perfect: usize,
+    pub great: usize,
+    pub good: usize,
+    pub miss: usize,
+}
+
+impl JudgementCounts {
+    pub fn add(&mut self, judgement: Judgement) {
+        match judgement {
+            Judgement::Perfect => self.perfect += 1,
+            Judgement::Great => self.great += 1,
+            Judgement::Good => self.good += 1,
+            Judgement::Miss => self.miss += 1,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.perfect + self.great + self.good + self.miss
+    }
+
+    /// Accuracy percentage (0-100), weighting each judgement by its score value like osu! does.
+    /// 100% before anything was judged
+    pub fn accuracy(&self) -> f32 {
+        if self.total() == 0 {
+            return 100.;
+        }
+
+        let earned = self.perfect * 300 + self.great * 100 + self.good * 50;
+        earned as f32 / (self.total() * 300) as f32 * 100.
+    }
+}
+
+/// Score earned by a judgement, given the combo before it.
+/// Longer combos make every cut worth more, so keeping the combo matters as much as being accurate
+pub fn score_for(judgement: Judgement, combo: usize) -> u64 {
+    let value = judgement.score_value();
+    value + value * combo as u64 / COMBO_SCORE_DIVISOR
+}
+//endregion
+
+//region Score State
+/// Everything about the score of the current run.
+/// The HUD and the results screen read from it
+#[derive(Debug, Clone)]
+pub struct ScoreState {
+    pub combo: usize,
+    pub max_combo: usize,
+    pub total_score: u64,
+    /// How many of each judgement were given (misses included)
+    pub judgements: JudgementCounts,
+    /// Timing error of every cut, in milliseconds (negative if early). Shown on the results screen
+    pub hit_errors: Vec<f64>,
+    /// Every cut's score is multiplied by it, see Mods::score_multiplier
+    pub multiplier: f64,
+}
+
+impl Default for ScoreState {
+    fn default() -> Self {
+        ScoreState::new(1.)
+    }
+}
+
+impl ScoreState {
+    pub fn new(multiplier: f64) -> Self {
+        ScoreState {
+            combo: 0,
+            max_combo: 0,
+            total_score: 0,
+            judgements: JudgementCounts::default(),
+            hit_errors: vec![],
+            multiplier,
+        }
+    }
+
+    /// Start over, with the same mods
+    pub fn reset(&mut self) {
+        *self = ScoreState::new(self.multiplier);
+    }
+
+    /// Count a judgement, scoring it with the current combo
+    pub fn judge(&mut self, judgement: Judgement) {
+        self.judgements.add(judgement);
+        self.total_score +=
+            (score_for(judgement, self.combo) as f64 * self.multiplier).round() as u64;
+
+        if judgement.breaks_combo() {
+            self.combo = 0;
+        } else {
+            self.combo += 1;
+            self.max_combo = self.max_combo.max(self.combo);
+        }
+    }
+
+    /// Count the judgement of a cut, remembering how far from its hit time it was
+    pub fn judge_hit(&mut self, judgement: Judgement, error: f64) {
+        self.judge(judgement);
+        if judgement != Judgement::Miss {
+            self.hit_errors.push(error);
+        }
+    }
+
+    /// Lose the combo without a judgement, e.g. when the player falls off the stage
+    pub fn break_combo(&mut self) {
+        self.combo = 0;
+    }
+
+    pub fn misses(&self) -> usize {
+        self.judgements.miss
+    }
+
+    pub fn accuracy(&self) -> f32 {
+        self.judgements.accuracy()
+    }
+
+    pub fn grade(&self) -> Grade {
+        Grade::from_judgements(&self.judgements)
+    }
+
+    /// Average timing error of the cuts, in milliseconds (negative if early on average)
+    pub fn mean_hit_error(&self) -> Option<f64> {
+        if self.hit_errors.is_empty() {
+            return None;
+        }
+
+        Some(self.hit_errors.iter().sum::<f64>() / self.hit_errors.len() as f64)
+    }
+}
+//endregion
+
+//region Grades
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grade {
+    SS,
+    S,
+    A,
+    B,
+    C,
+    D,
+}
+
+impl Grade {
+    /// Grade a run the way osu! standard does, by its ratio of perfects and its misses
+    pub fn from_judgements(judgements: &JudgementCounts) -> Self {
+        let total = judgements.total();
+        if total == 0 || judgements.perfect == total {
+            return Grade::SS;
+        }
+
+        let perfect_ratio = judgements.perfect as f32 / total as f32;
+        let good_ratio = judgements.good as f32 / total as f32;
+        let no_miss = judgements.miss == 0;
+
+        if perfect_ratio > 0.9 && good_ratio <= 0.01 && no_miss {
+            Grade::S
+        } else if (perfect_ratio > 0.8 && no_miss) || perfect_ratio > 0.9 {
+            Grade::A
+        } else if (perfect_ratio > 0.7 && no_miss) || perfect_ratio > 0.8 {
+            Grade::B
+        } else if perfect_ratio > 0.6 {
+            Grade::C
+        } else {
+            Grade::D
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            Grade::SS => Color::rgb(1., 0.95, 0.6),
+            Grade::S => Color::GOLD,
+            Grade::A => Color::rgb(0.4, 0.9, 0.4),
+            Grade::B => Color::rgb(0.3, 0.6, 1.),
+            Grade::C => Color::rgb(0.8, 0.4, 0.9),
+            Grade::D => Color::rgb(0.9, 0.3, 0.3),
+        }
+    }
+}
+
+impl fmt::Display for Grade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+//endregion
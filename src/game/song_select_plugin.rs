@@ -0,0 +1,452 @@
+use std::path::Path;
+
+use bevy::prelude::*;
+
+use crate::GameStates;
+
+use super::beatmap_library::{BeatmapLibrary, SelectedBeatmap};
+use super::high_scores::{BeatmapKey, HighScores};
+use super::mods::{Mod, Mods};
+use super::osz_import::ImportedBeatmapSets;
+use super::ui_plugin::{button, button_text};
+use super::{despawn_with, FontHandles, BEATMAP_LIBRARY_PATH, HIGH_SCORES_SHOWN};
+
+/// How many songs are listed at once, centered on the selected one (odd, so the selection is in the middle)
+const SONG_LIST_LINES: i32 = 9;
+
+//region Plugin Boilerplate
+pub struct SongSelectPlugin;
+
+impl Plugin for SongSelectPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(BeatmapLibrary::default())
+            .insert_resource(SongSelectCursor::default())
+            .add_system_set(
+                SystemSet::on_enter(GameStates::SongSelect).with_system(song_select_setup_system),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameStates::SongSelect)
+                    .with_system(refresh_library_system)
+                    .with_system(song_select_keyboard_system)
+                    .with_system(open_controls_menu_system)
+                    .with_system(back_to_main_menu_system)
+                    .with_system(song_list_click_system)
+                    .with_system(song_select_text_system)
+                    .with_system(song_select_scores_system)
+                    .with_system(mod_select_system)
+                    .with_system(mod_buttons_system),
+            )
+            // The controls menu is pushed on top of the song select
+            .add_system_set(
+                SystemSet::on_pause(GameStates::SongSelect).with_system(despawn_with::<SongSelectEntity>),
+            )
+            .add_system_set(
+                SystemSet::on_resume(GameStates::SongSelect).with_system(song_select_setup_system),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameStates::SongSelect)
+                    .with_system(song_select_exit_system)
+                    .with_system(despawn_with::<SongSelectEntity>),
+            );
+    }
+}
+//endregion
+
+//region Song Select Resources and Components
+/// The highlighted set and difficulty. Kept between visits so the player lands on the last played map
+#[derive(Default)]
+pub struct SongSelectCursor {
+    pub set: usize,
+    pub difficulty: usize,
+}
+
+impl SongSelectCursor {
+    /// Keep the selection inside the library, which may have shrunk since it was made
+    fn clamp_to(&mut self, library: &BeatmapLibrary) {
+        self.set = self.set.min(library.sets.len().saturating_sub(1));
+        let difficulties = library.sets.get(self.set).map_or(0, |set| set.difficulties.len());
+        self.difficulty = self.difficulty.min(difficulties.saturating_sub(1));
+    }
+}
+
+/// Everything spawned by the song select screen, despawned when leaving it
+#[derive(Component)]
+struct SongSelectEntity;
+
+/// A line of the song list, `offset` lines away from the selected song
+#[derive(Component)]
+struct SongListButton(i32);
+
+#[derive(Component)]
+struct DifficultyText;
+
+/// The local high scores of the selected difficulty
+#[derive(Component)]
+struct HighScoresText;
+
+#[derive(Component)]
+struct ModButton(Mod);
+
+#[derive(Component)]
+struct ScoreMultiplierText;
+//endregion
+
+fn song_select_setup_system(
+    mut commands: Commands,
+    fonts: Res<FontHandles>,
+    mut windows: ResMut<Windows>,
+    mut library: ResMut<BeatmapLibrary>,
+    mut cursor: ResMut<SongSelectCursor>,
+    mut keyboard: ResMut<Input<KeyCode>>,
+) {
+    // Menus are played with the mouse too
+    windows.get_primary_mut().unwrap().set_cursor_visibility(true);
+
+    // Escape brings the player here from other screens, it must not also go back to the main menu
+    keyboard.clear();
+
+    // Pick up beatmaps that were added while the game was running
+    *library = BeatmapLibrary::scan(Path::new(BEATMAP_LIBRARY_PATH));
+    cursor.clamp_to(&library);
+
+    let font = fonts.rubik_regular.clone();
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+            },
+            color: UiColor(Color::rgba(0., 0., 0., 0.0)),
+            ..Default::default()
+        })
+        .insert(SongSelectEntity)
+        .with_children(|parent| {
+            // Title
+            parent.spawn_bundle(
+                TextBundle::from_section(
+                    "Song Select",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 48.,
+                        color: Color::WHITE,
+                    },
+                )
+                .with_style(Style {
+                    margin: UiRect::all(Val::Px(10.)),
+                    ..Default::default()
+                }),
+            );
+
+            // Song list
+            for offset in -(SONG_LIST_LINES / 2)..=(SONG_LIST_LINES / 2) {
+                parent
+                    .spawn_bundle(ButtonBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(700.), Val::Px(36.)),
+                            margin: UiRect::all(Val::Px(2.)),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..Default::default()
+                        },
+                        ..button(Color::DARK_GRAY)
+                    })
+                    .with_children(|parent| {
+                        parent.spawn_bundle(button_text(Color::WHITE, &font, ""));
+                    })
+                    .insert(SongListButton(offset));
+            }
+
+            // Difficulty
+            parent
+                .spawn_bundle(button_text(Color::WHITE, &font, ""))
+                .insert(DifficultyText);
+
+            parent
+                .spawn_bundle(button_text(Color::GRAY, &font, ""))
+                .insert(HighScoresText);
+
+            // Mods
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Auto, Val::Auto),
+                        margin: UiRect::all(Val::Px(10.)),
+                        ..Default::default()
+                    },
+                    color: UiColor(Color::rgba(0., 0., 0., 0.0)),
+                    ..Default::default()
+                })
+                .with_children(|parent| {
+                    for (index, game_mod) in Mod::ALL.iter().enumerate() {
+                        parent
+                            .spawn_bundle(ButtonBundle {
+                                style: Style {
+                                    size: Size::new(Val::Px(130.), Val::Px(32.)),
+                                    margin: UiRect::all(Val::Px(3.)),
+                                    justify_content: JustifyContent::Center,
+                                    align_items: AlignItems::Center,
+                                    ..Default::default()
+                                },
+                                ..button(Color::DARK_GRAY)
+                            })
+                            .with_children(|parent| {
+                                parent.spawn_bundle(button_text(
+                                    Color::WHITE,
+                                    &font,
+                                    &format!("F{} {}", index + 1, game_mod.name()),
+                                ));
+                            })
+                            .insert(ModButton(*game_mod));
+                    }
+                });
+
+            parent
+                .spawn_bundle(button_text(Color::GRAY, &font, ""))
+                .insert(ScoreMultiplierText);
+
+            parent.spawn_bundle(button_text(
+                Color::GRAY,
+                &font,
+                "Up/Down: song   Left/Right: difficulty   Enter: play   F10: controls   Esc: back   (drop a .osz onto the window to import it)",
+            ));
+        });
+}
+
+fn song_select_exit_system(mut windows: ResMut<Windows>) {
+    // The game uses the Aim sprite instead of the cursor
+    windows.get_primary_mut().unwrap().set_cursor_visibility(false);
+}
+
+fn refresh_library_system(
+    imported: Res<ImportedBeatmapSets>,
+    mut library: ResMut<BeatmapLibrary>,
+    mut cursor: ResMut<SongSelectCursor>,
+) {
+    // A new archive was imported
+    if imported.is_changed() && !imported.is_added() {
+        *library = BeatmapLibrary::scan(Path::new(BEATMAP_LIBRARY_PATH));
+        cursor.clamp_to(&library);
+    }
+}
+
+fn song_select_keyboard_system(
+    keyboard: Res<Input<KeyCode>>,
+    library: Res<BeatmapLibrary>,
+    mut cursor: ResMut<SongSelectCursor>,
+    mut commands: Commands,
+    mut game_state: ResMut<State<GameStates>>,
+) {
+    if library.is_empty() {
+        return;
+    }
+
+    if keyboard.just_pressed(KeyCode::Down) {
+        move_selection(&mut cursor, &library, 1);
+    }
+    if keyboard.just_pressed(KeyCode::Up) {
+        move_selection(&mut cursor, &library, -1);
+    }
+
+    let difficulties = library.sets.get(cursor.set).map_or(0, |set| set.difficulties.len());
+    if keyboard.just_pressed(KeyCode::Right) {
+        cursor.difficulty = (cursor.difficulty + 1).min(difficulties.saturating_sub(1));
+    }
+    if keyboard.just_pressed(KeyCode::Left) {
+        cursor.difficulty = cursor.difficulty.saturating_sub(1);
+    }
+
+    if keyboard.just_pressed(KeyCode::Return) {
+        play_selected(&mut commands, &library, &cursor, &mut game_state);
+    }
+}
+
+fn back_to_main_menu_system(keyboard: Res<Input<KeyCode>>, mut game_state: ResMut<State<GameStates>>) {
+    if keyboard.just_pressed(KeyCode::Escape) {
+        game_state.overwrite_set(GameStates::MainMenu).unwrap();
+    }
+}
+
+fn open_controls_menu_system(keyboard: Res<Input<KeyCode>>, mut game_state: ResMut<State<GameStates>>) {
+    if keyboard.just_pressed(KeyCode::F10) {
+        game_state.overwrite_push(GameStates::Controls).unwrap();
+    }
+}
+
+fn song_list_click_system(
+    buttons: Query<(&Interaction, &SongListButton), Changed<Interaction>>,
+    library: Res<BeatmapLibrary>,
+    mut cursor: ResMut<SongSelectCursor>,
+    mut commands: Commands,
+    mut game_state: ResMut<State<GameStates>>,
+) {
+    if library.is_empty() {
+        return;
+    }
+
+    for (interaction, song_button) in buttons.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+
+        // Clicking the selected song plays it, clicking any other one selects it
+        if song_button.0 == 0 {
+            play_selected(&mut commands, &library, &cursor, &mut game_state);
+        } else {
+            move_selection(&mut cursor, &library, song_button.0);
+        }
+        return;
+    }
+}
+
+fn song_select_text_system(
+    library: Res<BeatmapLibrary>,
+    mut cursor: ResMut<SongSelectCursor>,
+    mut buttons: Query<(&SongListButton, &Children, &mut UiColor)>,
+    mut difficulty_text: Query<&mut Text, (With<DifficultyText>, Without<SongListButton>)>,
+    mut text_query: Query<&mut Text, Without<DifficultyText>>,
+) {
+    cursor.clamp_to(&library);
+
+    for (song_button, children, mut color) in buttons.iter_mut() {
+        let index = cursor.set as i32 + song_button.0;
+        let line = if library.is_empty() && song_button.0 == 0 {
+            format!("No beatmaps found in {BEATMAP_LIBRARY_PATH}")
+        } else if index >= 0 && (index as usize) < library.sets.len() {
+            let set = library.sets[index as usize].first();
+            format!("{} - {} ({})", set.artist, set.title, set.creator)
+        } else {
+            String::new()
+        };
+
+        *color = UiColor(if song_button.0 == 0 {
+            Color::GRAY
+        } else if line.is_empty() {
+            Color::rgba(0., 0., 0., 0.0)
+        } else {
+            Color::DARK_GRAY
+        });
+
+        if let Ok(mut text) = text_query.get_mut(children[0]) {
+            text.sections[0].value = line;
+        }
+    }
+
+    for mut text in difficulty_text.iter_mut() {
+        text.sections[0].value = match library.sets.get(cursor.set) {
+            Some(set) => match set.difficulties.get(cursor.difficulty) {
+                Some(beatmap) => format!(
+                    "<  {}  >  ({}/{})",
+                    beatmap.version,
+                    cursor.difficulty + 1,
+                    set.difficulties.len()
+                ),
+                None => String::new(),
+            },
+            None => String::new(),
+        };
+    }
+}
+
+fn song_select_scores_system(
+    library: Res<BeatmapLibrary>,
+    cursor: Res<SongSelectCursor>,
+    high_scores: Res<HighScores>,
+    mut query: Query<&mut Text, With<HighScoresText>>,
+) {
+    let beatmap = library
+        .sets
+        .get(cursor.set)
+        .and_then(|set| set.difficulties.get(cursor.difficulty));
+
+    for mut text in query.iter_mut() {
+        text.sections[0].value = match beatmap {
+            Some(beatmap) => {
+                let top_scores = high_scores.top(&BeatmapKey::from(beatmap), HIGH_SCORES_SHOWN);
+                if top_scores.is_empty() {
+                    "No local scores yet".to_string()
+                } else {
+                    top_scores
+                        .iter()
+                        .enumerate()
+                        .map(|(rank, high_score)| format!("#{}  {}", rank + 1, high_score.summary()))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                }
+            }
+            None => String::new(),
+        };
+    }
+}
+
+/// Mods are toggled by clicking them, or with F1-F5 in the order they are shown
+fn mod_select_system(
+    keyboard: Res<Input<KeyCode>>,
+    buttons: Query<(&Interaction, &ModButton), Changed<Interaction>>,
+    mut mods: ResMut<Mods>,
+) {
+    const MOD_KEYS: [KeyCode; 5] = [KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5];
+
+    for (key, game_mod) in MOD_KEYS.iter().zip(Mod::ALL) {
+        if keyboard.just_pressed(*key) {
+            mods.toggle(game_mod);
+        }
+    }
+
+    for (interaction, mod_button) in buttons.iter() {
+        if *interaction == Interaction::Clicked {
+            mods.toggle(mod_button.0);
+        }
+    }
+}
+
+fn mod_buttons_system(
+    mods: Res<Mods>,
+    mut buttons: Query<(&ModButton, &Interaction, &mut UiColor)>,
+    mut multiplier_text: Query<&mut Text, With<ScoreMultiplierText>>,
+) {
+    for (mod_button, interaction, mut color) in buttons.iter_mut() {
+        *color = UiColor(if mods.is_enabled(mod_button.0) {
+            Color::rgb(0.8, 0.55, 0.1)
+        } else if *interaction == Interaction::Hovered {
+            Color::GRAY
+        } else {
+            Color::DARK_GRAY
+        });
+    }
+
+    for mut text in multiplier_text.iter_mut() {
+        text.sections[0].value = format!("Score multiplier: {:.2}x", mods.score_multiplier());
+    }
+}
+
+fn move_selection(cursor: &mut SongSelectCursor, library: &BeatmapLibrary, offset: i32) {
+    let new_set = (cursor.set as i32 + offset).clamp(0, library.sets.len() as i32 - 1) as usize;
+
+    if new_set != cursor.set {
+        cursor.set = new_set;
+        cursor.difficulty = 0;
+    }
+}
+
+fn play_selected(
+    commands: &mut Commands,
+    library: &BeatmapLibrary,
+    cursor: &SongSelectCursor,
+    game_state: &mut State<GameStates>,
+) {
+    let beatmap = match library
+        .sets
+        .get(cursor.set)
+        .and_then(|set| set.difficulties.get(cursor.difficulty))
+    {
+        Some(beatmap) => beatmap.clone(),
+        None => return,
+    };
+
+    commands.insert_resource(SelectedBeatmap(beatmap));
+    game_state.overwrite_set(GameStates::Loading).unwrap();
+}
@@ -0,0 +1,128 @@
+use super::FRUIT_CUTTABLE_SPEED;
+
+// Velocities and gravities are in the units used by Velocity and GravityAffects:
+// pixels per 1/60th of a second (see move_with_velocity_system)
+const FRAMES_PER_SECOND: f32 = 60.;
+
+/// How a fruit has to be launched to be cut on time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FruitLaunch {
+    /// Initial vertical velocity
+    pub velocity: f32,
+    /// Seconds between the spawn and the moment the fruit becomes cuttable
+    pub rise_time: f32,
+}
+
+/// Solve the launch of a fruit spawned at `spawn_height` that has to become cuttable at `cut_height`,
+/// when it rises with a gravity of `gravity_up` (FRUITS_GRAVITY_UP, scaled by the mods).
+///
+/// While rising, fruits are pulled by FRUITS_GRAVITY_UP until their speed drops to
+/// FRUIT_CUTTABLE_SPEED, which is the hit time (they can already be cut from the start of the good
+/// window), and switch to FRUITS_GRAVITY_HOLD, then FRUITS_GRAVITY_FALL. So only the first phase matters
+/// to reach the cut height on time:
+/// v² = u² - 2gs gives the launch velocity, and v = u - gt the time it takes
+pub fn solve_launch(spawn_height: f32, cut_height: f32, gravity_up: f32) -> FruitLaunch {
+    let distance = (cut_height - spawn_height).max(0.);
+    let velocity =
+        (FRUIT_CUTTABLE_SPEED.powi(2) + 2. * gravity_up * distance).sqrt();
+    let rise_frames = (velocity - FRUIT_CUTTABLE_SPEED) / gravity_up;
+
+    FruitLaunch {
+        velocity,
+        rise_time: rise_frames / FRAMES_PER_SECOND,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{
+        FRUITS_GRAVITY_FALL, FRUITS_GRAVITY_HOLD, FRUITS_GRAVITY_UP, PHYSICS_STEP_SCALE, PHYSICS_TIMESTEP,
+    };
+
+    const STEP: f32 = PHYSICS_TIMESTEP as f32;
+
+    /// Fly a fruit step by step the way PhysicsStage does, returning after how many steps
+    /// and where it slowed down to FRUIT_CUTTABLE_SPEED, which is its hit time
+    fn simulate(spawn_height: f32, launch: FruitLaunch, gravity_scale: f32) -> (u32, f32) {
+        let (mut height, mut velocity) = (spawn_height, launch.velocity);
+        let mut gravity = FRUITS_GRAVITY_UP * gravity_scale;
+
+        for step in 1..=(10. / STEP) as u32 {
+            // gravity_system and move_with_velocity_system
+            velocity -= gravity * PHYSICS_STEP_SCALE;
+            height += velocity * PHYSICS_STEP_SCALE;
+
+            // fruits_cuttable_system, on the next step
+            let slowed_down = velocity <= FRUIT_CUTTABLE_SPEED;
+            gravity = gravity_scale
+                * if velocity < 0. {
+                    FRUITS_GRAVITY_FALL
+                } else if slowed_down {
+                    FRUITS_GRAVITY_HOLD
+                } else {
+                    FRUITS_GRAVITY_UP
+                };
+
+            if slowed_down {
+                return (step, height);
+            }
+        }
+
+        panic!("The fruit never slowed down");
+    }
+
+    /// Whether `steps` fixed steps land within one step of `rise_time`
+    fn within_a_step(steps: u32, rise_time: f32) -> bool {
+        (steps as f32 * STEP - rise_time).abs() < STEP
+    }
+
+    #[test]
+    fn fruits_become_cuttable_on_time() {
+        let spawn_height = -410.;
+
+        for cut_height in [-300., -100., 0., 150., 300.] {
+            let launch = solve_launch(spawn_height, cut_height, FRUITS_GRAVITY_UP);
+            let (steps, height) = simulate(spawn_height, launch, 1.);
+
+            assert!(
+                within_a_step(steps, launch.rise_time),
+                "cut at {cut_height}: expected {}s, got {steps} steps",
+                launch.rise_time
+            );
+            // Discrete steps overshoot the curve by about half a step of movement
+            assert!(
+                (height - cut_height).abs() < launch.velocity,
+                "expected a cut at {cut_height}, got {height}"
+            );
+        }
+    }
+
+    #[test]
+    fn timing_holds_for_every_cut_height() {
+        // The whole effective playfield of a 1080p window, in pixels
+        for cut_height in (-430..=430).step_by(10) {
+            let launch = solve_launch(-590., cut_height as f32, FRUITS_GRAVITY_UP);
+            let (steps, _) = simulate(-590., launch, 1.);
+            assert!(within_a_step(steps, launch.rise_time), "cut at {cut_height}");
+        }
+    }
+
+    #[test]
+    fn timing_holds_with_scaled_gravity() {
+        // Easy and Hard Rock
+        for gravity_scale in [0.7, 1.4] {
+            let launch = solve_launch(-410., 200., FRUITS_GRAVITY_UP * gravity_scale);
+            let (steps, _) = simulate(-410., launch, gravity_scale);
+            assert!(within_a_step(steps, launch.rise_time));
+        }
+    }
+
+    #[test]
+    fn targets_below_the_spawn_are_cuttable_right_away() {
+        let launch = solve_launch(0., -100., FRUITS_GRAVITY_UP);
+
+        assert_eq!(launch.velocity, FRUIT_CUTTABLE_SPEED);
+        assert_eq!(launch.rise_time, 0.);
+    }
+}
@@ -1,8 +1,10 @@
-use crate::game::Score;
+use crate::game::health_plugin::Health;
+use crate::game::scoring::ScoreState;
 use crate::GameStates;
 use bevy::prelude::*;
 
-use super::{GameSettings, FontHandles};
+use super::common_components::GameEntity;
+use super::{DashScheme, GameSettings, FontHandles};
 
 //region Plugin boilerplate
 pub struct UIPlugin;
@@ -16,6 +18,7 @@ impl Plugin for UIPlugin {
         .add_system_set(
             SystemSet::on_update(GameStates::Game) // Normal systems
                 .with_system(ui_update_system)
+                .with_system(health_bar_system)
                 .with_system(button_system)
                 .with_system(ui_post_setup_system)
                 .with_system(button_press_system),
@@ -28,6 +31,9 @@ impl Plugin for UIPlugin {
 #[derive(Component)]
 pub struct ScoreText;
 
+#[derive(Component)]
+pub struct HealthBar;
+
 #[derive(Component)]
 pub struct ToggleButton(String, bool);
 
@@ -35,7 +41,7 @@ pub struct ToggleButton(String, bool);
 pub enum SettingsButton {
     DashStop,
     SnapOnCut,
-    NoDeathPenalty,
+    MouseDash,
 }
 //endregion
 
@@ -62,7 +68,41 @@ fn ui_setup_system(
                 ..default()
             }),
         )
-        .insert(ScoreText);
+        .insert(ScoreText)
+        .insert(GameEntity);
+
+    // HP bar
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                // region Align to Top-Left
+                position: UiRect {
+                    left: Val::Px(20.),
+                    top: Val::Px(20.),
+                    ..default()
+                },
+                // endregion
+                size: Size::new(Val::Px(300.), Val::Px(16.)),
+                padding: UiRect::all(Val::Px(2.)),
+                ..default()
+            },
+            color: UiColor(Color::rgba(0., 0., 0., 0.6)),
+            ..default()
+        })
+        .insert(GameEntity)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                        ..default()
+                    },
+                    color: UiColor(Color::WHITE),
+                    ..default()
+                })
+                .insert(HealthBar);
+        });
 
     commands
         .spawn_bundle(NodeBundle {
@@ -78,6 +118,7 @@ fn ui_setup_system(
             color: UiColor(Color::rgba(0., 0., 0., 0.0)),
             ..Default::default()
         })
+        .insert(GameEntity)
         .with_children(|parent| {
             parent
                 .spawn_bundle(menu_background(Color::BLACK))
@@ -130,22 +171,22 @@ fn ui_setup_system(
                         ))
                         .insert(SettingsButton::SnapOnCut);
 
-                    // Snap on Cut Button
+                    // Mouse Dash Button, replaces the dash keys with clicking toward the cursor
                     parent
                         .spawn_bundle(button(Color::DARK_GRAY))
                         .with_children(|parent| {
                             parent.spawn_bundle(button_text(Color::WHITE, &font, ""));
                         })
                         .insert(ToggleButton(
-                            "No Death Penalty".to_string(),
-                            game_settings.no_death_penalty,
+                            "Mouse Dash".to_string(),
+                            game_settings.dash_scheme == DashScheme::Mouse,
                         ))
-                        .insert(SettingsButton::NoDeathPenalty);
+                        .insert(SettingsButton::MouseDash);
                 });
         });
 }
 
-fn button_text(color: Color, font: &Handle<Font>, text: &str) -> TextBundle {
+pub fn button_text(color: Color, font: &Handle<Font>, text: &str) -> TextBundle {
     TextBundle::from_section(
         text,
         TextStyle {
@@ -160,7 +201,7 @@ fn button_text(color: Color, font: &Handle<Font>, text: &str) -> TextBundle {
     })
 }
 
-fn button(color: Color) -> ButtonBundle {
+pub fn button(color: Color) -> ButtonBundle {
     ButtonBundle {
         style: Style {
             padding: UiRect::all(Val::Px(3.)),
@@ -171,7 +212,7 @@ fn button(color: Color) -> ButtonBundle {
     }
 }
 
-fn menu_background(color: Color) -> NodeBundle {
+pub fn menu_background(color: Color) -> NodeBundle {
     NodeBundle {
         style: Style {
             size: Size::new(Val::Px(200.0), Val::Auto),
@@ -184,7 +225,7 @@ fn menu_background(color: Color) -> NodeBundle {
     }
 }
 
-fn button_system(
+pub fn button_system(
     mut buttons: Query<(&Interaction, &mut UiColor), (Changed<Interaction>, With<Button>)>,
 ) {
     for (interaction, mut color) in buttons.iter_mut() {
@@ -221,13 +262,16 @@ fn button_press_system(
             match settings {
                 SettingsButton::DashStop => game_settings.dash_stop = toggle.1,
                 SettingsButton::SnapOnCut => game_settings.snap_on_cut = toggle.1,
-                SettingsButton::NoDeathPenalty => game_settings.no_death_penalty = toggle.1,
+                SettingsButton::MouseDash => {
+                    game_settings.dash_scheme =
+                        if toggle.1 { DashScheme::Mouse } else { DashScheme::Arrows }
+                }
             };
         }
     }
 }
 
-fn default_style() -> Style {
+pub fn default_style() -> Style {
     Style {
         size: Size::new(Val::Percent(100.), Val::Percent(100.)),
         justify_content: JustifyContent::Center,
@@ -236,8 +280,27 @@ fn default_style() -> Style {
     }
 }
 
-fn ui_update_system(mut query: Query<&mut Text, With<ScoreText>>, score: Res<Score>) {
+fn ui_update_system(
+    mut query: Query<&mut Text, With<ScoreText>>,
+    score: Res<ScoreState>,
+) {
     query.for_each_mut(|mut score_text| {
-        score_text.sections[0].value = format!("Combo: {}", score.0.to_string())
+        score_text.sections[0].value = format!(
+            "{}\nCombo: {}\n{:.2}%",
+            score.total_score,
+            score.combo,
+            score.accuracy()
+        )
     });
 }
+
+fn health_bar_system(
+    mut query: Query<(&mut Style, &mut UiColor), With<HealthBar>>,
+    health: Res<Health>,
+) {
+    for (mut style, mut color) in query.iter_mut() {
+        style.size.width = Val::Percent(health.0 * 100.);
+        // Turns red as it empties
+        *color = UiColor(Color::rgb(1., health.0.sqrt(), health.0));
+    }
+}
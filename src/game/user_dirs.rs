@@ -0,0 +1,38 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Folder name used inside the platform's data and config directories
+const APP_DIRECTORY: &str = "need_4_fruits";
+
+/// Where the game keeps what it records, such as high scores
+/// (e.g. ~/.local/share/need_4_fruits). Falls back to the working directory
+pub fn data_dir() -> PathBuf {
+    dirs::data_dir()
+        .map(|dir| dir.join(APP_DIRECTORY))
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Where the settings are saved (e.g. ~/.config/need_4_fruits). Falls back to the working directory
+pub fn config_dir() -> PathBuf {
+    dirs::config_dir()
+        .map(|dir| dir.join(APP_DIRECTORY))
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Write a file so that it is never left half written: the contents go to a temporary file
+/// next to it, which then replaces the old file in a single rename
+pub fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let temporary_path = path.with_extension("tmp");
+    {
+        let mut file = fs::File::create(&temporary_path)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+    }
+
+    fs::rename(&temporary_path, path)
+}
@@ -1,4 +1,4 @@
-use bevy::{prelude::*, window::{PresentMode, WindowMode}};
+use bevy::{prelude::*, window::PresentMode};
 use bevy_kira_audio::AudioPlugin;
 
 mod game;
@@ -7,11 +7,27 @@ mod main_menu;
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
 pub enum GameStates {
         MainMenu,
+        SongSelect,
         Loading,
         Game,
+        Results,
+        Controls,
+        // Pushed on top of Game, which stays frozen underneath
+        Paused,
 }
 
 fn main() {
+    // Diagnostic mode: print what the game understands of a beatmap, then quit
+    // Usage: need_4_fruits --dump-beatmap path/to/beatmap.osu
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(index) = args.iter().position(|arg| arg == "--dump-beatmap") {
+        dump_beatmap(args.get(index + 1));
+        return;
+    }
+
+    // Read before the window opens, which uses its window mode
+    let config = game::config::Config::load();
+
         App::new()
         .insert_resource(ClearColor(Color::rgb(0.3, 0.2, 0.4)))
         .insert_resource(WindowDescriptor {
@@ -26,20 +42,34 @@ fn main() {
             decorations: true,
             cursor_visible: false,
             cursor_locked: false,
-            mode: WindowMode::Windowed,
+            mode: config.window_mode,
             transparent: false,
             canvas: None,
             fit_canvas_to_parent: false
         })
-        .add_state(GameStates::Loading)
+        .insert_resource(config)
+        .add_state(GameStates::MainMenu)
         .add_plugins(DefaultPlugins)
         .add_plugin(AudioPlugin)
         .add_plugin(game::MainPlugin)
+        .add_plugin(main_menu::MainMenuPlugin)
         .run();
 }
 
-fn killall_system(mut commands: Commands, query: Query<Entity>) {
-    for ent in query.iter() {
-        commands.entity(ent).despawn();
+fn dump_beatmap(path: Option<&String>) {
+    let path = match path {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: --dump-beatmap <path to .osu file>");
+            std::process::exit(2);
+        }
+    };
+
+    match game::osu_reader::open_osu(path) {
+        Ok(beatmap) => println!("{}", beatmap.debug_report()),
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
     }
-}
\ No newline at end of file
+}
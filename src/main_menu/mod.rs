@@ -1 +1,281 @@
+use std::path::Path;
+
+use bevy::app::AppExit;
 use bevy::prelude::*;
+use bevy_kira_audio::{AudioChannel, AudioControl};
+
+use crate::game::beatmap_library::{BeatmapLibrary, LibraryBeatmap};
+use crate::game::beatmap_plugin::MusicChannel;
+use crate::game::high_scores::{BeatmapKey, HighScores};
+use crate::game::song_select_plugin::SongSelectCursor;
+use crate::game::ui_plugin::{button, button_system, button_text};
+use crate::game::{despawn_with, FontHandles, BEATMAP_LIBRARY_PATH};
+use crate::GameStates;
+
+/// How many personal bests the scores panel lists
+const MAIN_MENU_SCORES_SHOWN: usize = 10;
+
+//region Plugin Boilerplate
+pub struct MainMenuPlugin;
+
+impl Plugin for MainMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(MenuPreview::default())
+            .add_system_set(
+                SystemSet::on_enter(GameStates::MainMenu)
+                    .with_system(main_menu_setup_system)
+                    .with_system(start_preview_system),
+            )
+            .add_system_set(
+                SystemSet::on_update(GameStates::MainMenu)
+                    .with_system(button_system)
+                    .with_system(main_menu_button_system)
+                    .with_system(now_playing_system)
+                    .with_system(main_menu_keyboard_system),
+            )
+            // The controls menu is pushed on top of the main menu, and the preview keeps playing
+            .add_system_set(
+                SystemSet::on_pause(GameStates::MainMenu).with_system(despawn_with::<MainMenuEntity>),
+            )
+            .add_system_set(
+                SystemSet::on_resume(GameStates::MainMenu).with_system(main_menu_setup_system),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameStates::MainMenu)
+                    .with_system(despawn_with::<MainMenuEntity>)
+                    .with_system(stop_preview_system),
+            );
+    }
+}
+//endregion
+
+//region Main Menu Resources and Components
+/// The beatmap whose song is playing behind the menu, if any
+#[derive(Default)]
+struct MenuPreview(Option<LibraryBeatmap>);
+
+/// Everything spawned by the main menu, despawned when leaving it
+#[derive(Component)]
+struct MainMenuEntity;
+
+#[derive(Component)]
+enum MainMenuButton {
+    Play,
+    Settings,
+    Scores,
+    Quit,
+}
+
+#[derive(Component)]
+struct NowPlayingText;
+
+/// The personal bests, shown with the Scores button
+#[derive(Component)]
+struct ScoresText;
+//endregion
+
+fn main_menu_setup_system(
+    mut commands: Commands,
+    fonts: Res<FontHandles>,
+    mut windows: ResMut<Windows>,
+) {
+    windows.get_primary_mut().unwrap().set_cursor_visibility(true);
+
+    let font = fonts.rubik_regular.clone();
+    let transparent = UiColor(Color::rgba(0., 0., 0., 0.0));
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+            },
+            color: transparent,
+            ..Default::default()
+        })
+        .insert(MainMenuEntity)
+        .with_children(|parent| {
+            // Title
+            parent.spawn_bundle(
+                TextBundle::from_section(
+                    "Need 4 Fruits",
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 72.,
+                        color: Color::WHITE,
+                    },
+                )
+                .with_style(Style {
+                    margin: UiRect::all(Val::Px(10.)),
+                    ..Default::default()
+                }),
+            );
+
+            parent
+                .spawn_bundle(button_text(Color::GRAY, &font, ""))
+                .insert(NowPlayingText);
+
+            for (label, menu_button) in [
+                ("Play", MainMenuButton::Play),
+                ("Settings", MainMenuButton::Settings),
+                ("Scores", MainMenuButton::Scores),
+                ("Quit", MainMenuButton::Quit),
+            ] {
+                parent
+                    .spawn_bundle(ButtonBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(260.), Val::Px(40.)),
+                            margin: UiRect::all(Val::Px(4.)),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..Default::default()
+                        },
+                        ..button(Color::DARK_GRAY)
+                    })
+                    .with_children(|parent| {
+                        parent.spawn_bundle(button_text(Color::WHITE, &font, label));
+                    })
+                    .insert(menu_button);
+            }
+
+            parent
+                .spawn_bundle(button_text(Color::GRAY, &font, "").with_style(Style {
+                    margin: UiRect::all(Val::Px(10.)),
+                    ..Default::default()
+                }))
+                .insert(ScoresText);
+        });
+}
+
+/// Play the song of the last selected beatmap from its preview point, like osu!'s main menu
+fn start_preview_system(
+    asset_server: Res<AssetServer>,
+    music_channel: Res<AudioChannel<MusicChannel>>,
+    mut library: ResMut<BeatmapLibrary>,
+    cursor: Res<SongSelectCursor>,
+    mut preview: ResMut<MenuPreview>,
+) {
+    // The main menu is the first screen, before the song select has scanned anything
+    if library.is_empty() {
+        *library = BeatmapLibrary::scan(Path::new(BEATMAP_LIBRARY_PATH));
+    }
+
+    preview.0 = library
+        .sets
+        .get(cursor.set)
+        .or_else(|| library.sets.first())
+        .and_then(|set| set.difficulties.get(cursor.difficulty).or_else(|| set.difficulties.first()))
+        .filter(|beatmap| !beatmap.audio_filename.is_empty())
+        .cloned();
+
+    let beatmap = match &preview.0 {
+        Some(beatmap) => beatmap,
+        None => return,
+    };
+
+    // -1 means the beatmap has no preview point
+    let start = beatmap.preview_time.max(0) as f64 / 1000.;
+    music_channel
+        .play(asset_server.load(&beatmap.asset_path(&beatmap.audio_filename)))
+        .start_from(start)
+        .looped();
+}
+
+fn now_playing_system(preview: Res<MenuPreview>, mut query: Query<&mut Text, With<NowPlayingText>>) {
+    for mut text in query.iter_mut() {
+        text.sections[0].value = match &preview.0 {
+            Some(beatmap) => format!("Now playing: {} - {}", beatmap.artist, beatmap.title),
+            None => String::new(),
+        };
+    }
+}
+
+fn stop_preview_system(
+    music_channel: Res<AudioChannel<MusicChannel>>,
+    mut preview: ResMut<MenuPreview>,
+) {
+    music_channel.stop();
+    preview.0 = None;
+}
+
+fn main_menu_button_system(
+    buttons: Query<(&Interaction, &MainMenuButton), Changed<Interaction>>,
+    library: Res<BeatmapLibrary>,
+    high_scores: Res<HighScores>,
+    mut scores_text: Query<&mut Text, With<ScoresText>>,
+    mut game_state: ResMut<State<GameStates>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    for (interaction, menu_button) in buttons.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+
+        match menu_button {
+            MainMenuButton::Play => game_state.overwrite_set(GameStates::SongSelect).unwrap(),
+            MainMenuButton::Settings => game_state.overwrite_push(GameStates::Controls).unwrap(),
+            MainMenuButton::Scores => {
+                for mut text in scores_text.iter_mut() {
+                    text.sections[0].value = if text.sections[0].value.is_empty() {
+                        personal_bests(&library, &high_scores)
+                    } else {
+                        String::new()
+                    };
+                }
+            }
+            MainMenuButton::Quit => exit.send(AppExit),
+        }
+        return;
+    }
+}
+
+fn main_menu_keyboard_system(
+    keyboard: Res<Input<KeyCode>>,
+    mut scores_text: Query<&mut Text, With<ScoresText>>,
+    mut game_state: ResMut<State<GameStates>>,
+) {
+    if keyboard.just_pressed(KeyCode::Return) {
+        game_state.overwrite_set(GameStates::SongSelect).unwrap();
+    } else if keyboard.just_pressed(KeyCode::Escape) {
+        // Hides the scores panel
+        for mut text in scores_text.iter_mut() {
+            text.sections[0].value = String::new();
+        }
+    }
+}
+
+/// The best score of every difficulty in the library that has been played, best first
+fn personal_bests(library: &BeatmapLibrary, high_scores: &HighScores) -> String {
+    let mut bests: Vec<_> = library
+        .sets
+        .iter()
+        .flat_map(|set| &set.difficulties)
+        .filter_map(|beatmap| {
+            high_scores
+                .personal_best(&BeatmapKey::from(beatmap))
+                .map(|high_score| (beatmap, high_score))
+        })
+        .collect();
+    if bests.is_empty() {
+        return "No local scores yet".to_string();
+    }
+
+    bests.sort_by(|(_, a), (_, b)| b.score.cmp(&a.score));
+    bests
+        .iter()
+        .take(MAIN_MENU_SCORES_SHOWN)
+        .map(|(beatmap, high_score)| {
+            format!(
+                "{} - {} [{}]  {}",
+                beatmap.artist,
+                beatmap.title,
+                beatmap.version,
+                high_score.summary()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}